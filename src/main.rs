@@ -3,21 +3,980 @@ use eframe::{egui, epaint::Color32, App, CreationContext};
 use egui::{RichText, ScrollArea};
 use egui_material_icons::icons::*;
 use rfd::FileDialog;
+use rodio::buffer::SamplesBuffer;
+use rodio::{OutputStream, OutputStreamBuilder};
 use serde::{Deserialize, Serialize};
+use std::cell::{OnceCell, RefCell};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::fs;
 use std::path::PathBuf;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
 // use egui::WidgetText::RichText;
 
 // Theme configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Theme {
     name: String,
     background_color: [u8; 3],
     text_color: [u8; 3],
     heading_colors: Vec<[u8; 3]>,
+    #[serde(default)]
+    heading_decoration: HeadingDecoration,
+    #[serde(default)]
+    inline_code_background: Option<[u8; 4]>, // unmultiplied RGBA
+    #[serde(default)]
+    inline_code_color: Option<[u8; 3]>,
+    #[serde(default)]
+    default_font_size: Option<f32>, // font size to switch to when the theme is picked
+    #[serde(default)]
+    accent_color: Option<[u8; 3]>, // highlight for links and markers
+    #[serde(default)]
+    heading_prefixes: Vec<String>, // text put before H1, H2 and so on; missing or empty for none
+    #[serde(default)]
+    role_colors: BTreeMap<String, [u8; 3]>, // named colors for heading roles
+    #[serde(default)]
+    alert_colors: BTreeMap<String, [u8; 3]>, // by alert type, like "note", for ones to recolor
+}
+
+// Background behind inline code for themes that don't set their own: a light tint on dark
+// backgrounds and a dark one on light backgrounds, so it shows up either way
+const DARK_INLINE_CODE_BACKGROUND: Color32 = Color32::from_rgba_premultiplied(80, 80, 80, 40);
+const LIGHT_INLINE_CODE_BACKGROUND: Color32 = Color32::from_black_alpha(24);
+
+impl Theme {
+    fn is_dark(&self) -> bool {
+        is_dark_color(self.background_color)
+    }
+
+    fn inline_code_background(&self) -> Color32 {
+        let default = if self.is_dark() {
+            DARK_INLINE_CODE_BACKGROUND
+        } else {
+            LIGHT_INLINE_CODE_BACKGROUND
+        };
+        self.inline_code_background.map_or(default, |[r, g, b, a]| {
+            Color32::from_rgba_unmultiplied(r, g, b, a)
+        })
+    }
+
+    // Color for links, markers and other highlights, the H1 color unless the theme sets one
+    fn accent_color(&self) -> Color32 {
+        let [r, g, b] = self
+            .accent_color
+            .or(self.heading_colors.first().copied())
+            .unwrap_or(self.text_color);
+        Color32::from_rgb(r, g, b)
+    }
+
+    fn alert_color(&self, kind: AlertKind) -> Color32 {
+        let [r, g, b] = self
+            .alert_colors
+            .get(kind.key())
+            .copied()
+            .unwrap_or(kind.default_color());
+        Color32::from_rgb(r, g, b)
+    }
+
+    // Inline code uses the surrounding text color unless the theme sets one
+    fn inline_code_color(&self, text_color: Color32) -> Color32 {
+        self.inline_code_color
+            .map_or(text_color, |[r, g, b]| Color32::from_rgb(r, g, b))
+    }
+}
+
+// Optional line drawn in the heading color to set headings apart
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum HeadingDecoration {
+    #[default]
+    None,
+    Underline,
+    AccentBar,
+}
+
+impl HeadingDecoration {
+    const ALL: [HeadingDecoration; 3] = [
+        HeadingDecoration::None,
+        HeadingDecoration::Underline,
+        HeadingDecoration::AccentBar,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            HeadingDecoration::None => "None",
+            HeadingDecoration::Underline => "Underline",
+            HeadingDecoration::AccentBar => "Accent Bar",
+        }
+    }
+}
+
+// Heading font size relative to body text for H1-H6
+const HEADING_SIZE_MULTIPLIERS: [f32; 6] = [2.0, 1.8, 1.6, 1.4, 1.2, 1.1];
+
+// Thickness of the heading underline or accent bar for H1-H6
+const HEADING_DECORATION_THICKNESS: [f32; 6] = [4.0, 3.0, 2.5, 2.0, 1.5, 1.0];
+
+// Space between a heading and its underline or accent bar
+const HEADING_DECORATION_GAP: f32 = 6.0;
+
+// Strength of the accent color behind the sentence at the focus line
+const SENTENCE_HIGHLIGHT_ALPHA: f32 = 0.18;
+
+// Strength of an alert's color in the background of its box
+const ALERT_FILL_ALPHA: f32 = 0.12;
+
+// The kinds of GitHub-style alert, as in "> [!NOTE]"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AlertKind {
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl AlertKind {
+    // The kind named by an alert's first line, without its ">"
+    fn parse(quoted: &str) -> Option<AlertKind> {
+        let name = quoted.trim().strip_prefix("[!")?.strip_suffix(']')?;
+        match name.to_ascii_uppercase().as_str() {
+            "NOTE" => Some(AlertKind::Note),
+            "TIP" => Some(AlertKind::Tip),
+            "IMPORTANT" => Some(AlertKind::Important),
+            "WARNING" => Some(AlertKind::Warning),
+            "CAUTION" => Some(AlertKind::Caution),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            AlertKind::Note => "Note",
+            AlertKind::Tip => "Tip",
+            AlertKind::Important => "Important",
+            AlertKind::Warning => "Warning",
+            AlertKind::Caution => "Caution",
+        }
+    }
+
+    // Name in a theme's alert_colors
+    fn key(self) -> &'static str {
+        match self {
+            AlertKind::Note => "note",
+            AlertKind::Tip => "tip",
+            AlertKind::Important => "important",
+            AlertKind::Warning => "warning",
+            AlertKind::Caution => "caution",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            AlertKind::Note => ICON_INFO,
+            AlertKind::Tip => ICON_LIGHTBULB,
+            AlertKind::Important => ICON_FEEDBACK,
+            AlertKind::Warning => ICON_WARNING,
+            AlertKind::Caution => ICON_REPORT,
+        }
+    }
+
+    // GitHub's colors, which read on light and dark backgrounds alike
+    fn default_color(self) -> [u8; 3] {
+        match self {
+            AlertKind::Note => [68, 147, 248],
+            AlertKind::Tip => [63, 185, 80],
+            AlertKind::Important => [171, 125, 248],
+            AlertKind::Warning => [210, 153, 34],
+            AlertKind::Caution => [248, 81, 73],
+        }
+    }
+}
+
+// Colored segments of a highlighted code block
+type HighlightedCode = Vec<(Color32, String)>;
+
+// Syntax highlighting for fenced code blocks. The syntax and theme sets are loaded on first
+// use and each block's result is cached, so nothing is reloaded or re-highlighted per frame
+#[derive(Default)]
+struct CodeHighlighter {
+    sets: OnceCell<(SyntaxSet, ThemeSet)>,
+    cache: RefCell<HashMap<(String, String, bool), Option<HighlightedCode>>>,
+}
+
+impl CodeHighlighter {
+    // Highlight code for a dark or light background, or None if the language is unknown
+    fn highlight(
+        &self,
+        language: &str,
+        code: &str,
+        dark_background: bool,
+    ) -> Option<HighlightedCode> {
+        let key = (language.to_string(), code.to_string(), dark_background);
+        if let Some(cached) = self.cache.borrow().get(&key) {
+            return cached.clone();
+        }
+
+        let highlighted = self.highlight_uncached(language, code, dark_background);
+        self.cache.borrow_mut().insert(key, highlighted.clone());
+        highlighted
+    }
+
+    fn highlight_uncached(
+        &self,
+        language: &str,
+        code: &str,
+        dark_background: bool,
+    ) -> Option<HighlightedCode> {
+        if language.is_empty() {
+            return None;
+        }
+
+        let (syntax_set, theme_set) = self.sets.get_or_init(|| {
+            (
+                SyntaxSet::load_defaults_newlines(),
+                ThemeSet::load_defaults(),
+            )
+        });
+        let syntax = syntax_set.find_syntax_by_token(language)?;
+        let theme_name = if dark_background {
+            "base16-ocean.dark"
+        } else {
+            "base16-ocean.light"
+        };
+        let mut highlighter = HighlightLines::new(syntax, &theme_set.themes[theme_name]);
+
+        let mut segments = Vec::new();
+        for line in LinesWithEndings::from(code) {
+            for (style, text) in highlighter.highlight_line(line, syntax_set).ok()? {
+                let color = style.foreground;
+                segments.push((
+                    Color32::from_rgb(color.r, color.g, color.b),
+                    text.to_string(),
+                ));
+            }
+        }
+        Some(segments)
+    }
+
+    fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+}
+
+// Sound effects. The audio output is opened the first time one plays, and without an output
+// device they're skipped
+#[derive(Default)]
+struct Sounds {
+    output: OnceCell<Option<OutputStream>>,
+}
+
+impl Sounds {
+    // The beep when playback stops at the end of the script
+    fn beep(&self) {
+        self.tone(880.0, 0.3);
+    }
+
+    // The metronome's click: a blip too short to hear as a note
+    fn click(&self) {
+        self.tone(1760.0, 0.02);
+    }
+
+    // Play a sine tone that fades out over its length, so it ends without a click
+    fn tone(&self, frequency: f32, seconds: f32) {
+        let output = self
+            .output
+            .get_or_init(|| match OutputStreamBuilder::open_default_stream() {
+                Ok(mut stream) => {
+                    stream.log_on_drop(false);
+                    Some(stream)
+                }
+                Err(e) => {
+                    eprintln!("No audio output for sounds: {}", e);
+                    None
+                }
+            });
+        let Some(output) = output else {
+            return;
+        };
+        let length = (SOUND_SAMPLE_RATE as f32 * seconds) as usize;
+        let samples: Vec<f32> = (0..length)
+            .map(|i| {
+                let time = i as f32 / SOUND_SAMPLE_RATE as f32;
+                let fade = 1.0 - i as f32 / length as f32;
+                (std::f32::consts::TAU * frequency * time).sin() * fade * SOUND_VOLUME
+            })
+            .collect();
+        output
+            .mixer()
+            .add(SamplesBuffer::new(1, SOUND_SAMPLE_RATE, samples));
+    }
+}
+
+// Sample rate of generated sounds, and their loudness from 0 to 1
+const SOUND_SAMPLE_RATE: u32 = 44_100;
+const SOUND_VOLUME: f32 = 0.3;
+
+// Number of numbered scroll markers, bound to keys 1-9
+const MARKER_COUNT: usize = 9;
+
+// A saved scroll position in one of the numbered marker slots
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Marker {
+    slot: usize,
+    position: f32,
+}
+
+// Settings remembered for an individual file, keyed by its path
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct FileSettings {
+    scroll_speed: Option<f32>,
+    font_size: Option<f32>,
+    theme: Option<String>,
+    markers: Vec<Marker>,
+    resume_position: Option<f32>, // where reading stopped, as a fraction of the content height
+    collapsed_sections: Vec<String>, // headings folded in the outline
+    words_per_minute: Option<f32>, // reading pace measured by calibration
+}
+
+// Progress of measuring how fast the script is read aloud: between the taps at the start and
+// end of reading, then the result waiting to be accepted or discarded
+#[derive(Debug, Clone, PartialEq)]
+enum Calibration {
+    Reading {
+        started: Instant,
+        start_offset: f32,
+    },
+    Done {
+        words_per_minute: f32,
+        speed: f32, // in settings.speed_unit
+    },
+    Failed(String),
+}
+
+// Longest time that can be given to finish the script in, in minutes
+const MAX_TARGET_MINUTES: f32 = 600.0;
+
+// Shortest reading that gives a useful calibration
+const MIN_CALIBRATION_SECONDS: f32 = 5.0;
+
+// Horizontal alignment for paragraphs and headings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum TextAlignment {
+    Left,
+    Center,
+    Right,
+    Justify,
+}
+
+impl TextAlignment {
+    const ALL: [TextAlignment; 4] = [
+        TextAlignment::Left,
+        TextAlignment::Center,
+        TextAlignment::Right,
+        TextAlignment::Justify,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            TextAlignment::Left => "Left",
+            TextAlignment::Center => "Center",
+            TextAlignment::Right => "Right",
+            TextAlignment::Justify => "Justify",
+        }
+    }
+
+    fn layout(self) -> egui::Layout {
+        match self {
+            TextAlignment::Left => egui::Layout::top_down(egui::Align::Min),
+            TextAlignment::Center => egui::Layout::top_down(egui::Align::Center),
+            TextAlignment::Right => egui::Layout::top_down(egui::Align::Max),
+            TextAlignment::Justify => egui::Layout::top_down_justified(egui::Align::Min),
+        }
+    }
+}
+
+// What clicking a link in the content does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum LinkAction {
+    #[default]
+    Open, // in the browser
+    Copy, // the URL to the clipboard
+    Ignore,
+}
+
+impl LinkAction {
+    const ALL: [LinkAction; 3] = [LinkAction::Open, LinkAction::Copy, LinkAction::Ignore];
+
+    fn label(self) -> &'static str {
+        match self {
+            LinkAction::Open => "Open in Browser",
+            LinkAction::Copy => "Copy URL",
+            LinkAction::Ignore => "Nothing",
+        }
+    }
+}
+
+// Which time the cue log exports for each heading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum CueTime {
+    #[default]
+    Elapsed, // playing time since the restart, pauses included
+    WallClock,
+}
+
+impl CueTime {
+    const ALL: [CueTime; 2] = [CueTime::Elapsed, CueTime::WallClock];
+
+    fn label(self) -> &'static str {
+        match self {
+            CueTime::Elapsed => "Elapsed Time",
+            CueTime::WallClock => "Wall Clock",
+        }
+    }
+}
+
+// A heading passing the focus line during playback
+struct CueLogEntry {
+    heading: String,
+    line: usize,  // source line of the heading
+    elapsed: f32, // seconds of playing since the log was reset
+    wall_clock: chrono::DateTime<chrono::Local>,
+}
+
+// What playback does when it reaches the end of the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum EndBehavior {
+    #[default]
+    Stop,
+    Loop,     // back to the top after settings.loop_delay
+    Hold,     // keep playing at the bottom, following anything added to the file
+    PingPong, // scroll back up to the top, then down again
+}
+
+impl EndBehavior {
+    const ALL: [EndBehavior; 4] = [
+        EndBehavior::Stop,
+        EndBehavior::Loop,
+        EndBehavior::Hold,
+        EndBehavior::PingPong,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            EndBehavior::Stop => "Stop",
+            EndBehavior::Loop => "Loop",
+            EndBehavior::Hold => "Hold at Bottom",
+            EndBehavior::PingPong => "Ping-Pong",
+        }
+    }
+}
+
+// When the content panel's scrollbar is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum ScrollbarMode {
+    #[default]
+    Shown,
+    HiddenWhilePlaying,
+    Hidden,
+}
+
+impl ScrollbarMode {
+    const ALL: [ScrollbarMode; 3] = [
+        ScrollbarMode::Shown,
+        ScrollbarMode::HiddenWhilePlaying,
+        ScrollbarMode::Hidden,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ScrollbarMode::Shown => "Shown",
+            ScrollbarMode::HiddenWhilePlaying => "Hidden While Playing",
+            ScrollbarMode::Hidden => "Hidden",
+        }
+    }
+
+    fn visibility(self, playing: bool) -> egui::scroll_area::ScrollBarVisibility {
+        use egui::scroll_area::ScrollBarVisibility;
+        match self {
+            ScrollbarMode::Hidden => ScrollBarVisibility::AlwaysHidden,
+            ScrollbarMode::HiddenWhilePlaying if playing => ScrollBarVisibility::AlwaysHidden,
+            _ => ScrollBarVisibility::VisibleWhenNeeded,
+        }
+    }
+
+    // Width kept clear of text for the scrollbar. It's only given back when the scrollbar is
+    // never shown, so starting and stopping playback doesn't rewrap the text
+    fn reserved_width(self) -> f32 {
+        match self {
+            ScrollbarMode::Hidden => 0.0,
+            _ => SCROLLBAR_WIDTH,
+        }
+    }
+}
+
+// Room left beside the content for the scrollbar
+const SCROLLBAR_WIDTH: f32 = 20.0;
+
+// Unit scroll speeds are given and stored in. Lines per second follow the font size, so a
+// speed reads at the same pace whatever the font size or display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+enum SpeedUnit {
+    #[default]
+    PixelsPerSecond,
+    LinesPerSecond,
+}
+
+impl SpeedUnit {
+    const ALL: [SpeedUnit; 2] = [SpeedUnit::PixelsPerSecond, SpeedUnit::LinesPerSecond];
+
+    fn label(self) -> &'static str {
+        match self {
+            SpeedUnit::PixelsPerSecond => "Pixels per second",
+            SpeedUnit::LinesPerSecond => "Lines per second",
+        }
+    }
+
+    fn suffix(self) -> &'static str {
+        match self {
+            SpeedUnit::PixelsPerSecond => "px/s",
+            SpeedUnit::LinesPerSecond => " lines/s",
+        }
+    }
+
+    fn format(self, speed: f32) -> String {
+        match self {
+            SpeedUnit::PixelsPerSecond => format!("{:.0}{}", speed, self.suffix()),
+            SpeedUnit::LinesPerSecond => format!("{:.2}{}", speed, self.suffix()),
+        }
+    }
+
+    // How fast the speed values change when dragged
+    fn drag_speed(self) -> f64 {
+        match self {
+            SpeedUnit::PixelsPerSecond => 1.0,
+            SpeedUnit::LinesPerSecond => 0.05,
+        }
+    }
+
+    // Lower and upper bounds for the speed limits themselves
+    fn limit_range(self) -> (f32, f32) {
+        match self {
+            SpeedUnit::PixelsPerSecond => (1.0, 5000.0),
+            SpeedUnit::LinesPerSecond => (0.1, 200.0),
+        }
+    }
+
+    // Default minimum, maximum and step
+    fn default_limits(self) -> (f32, f32, f32) {
+        match self {
+            SpeedUnit::PixelsPerSecond => (10.0, 500.0, 10.0),
+            SpeedUnit::LinesPerSecond => (0.5, 20.0, 0.5),
+        }
+    }
+}
+
+// Height of a line of text relative to the font size
+const LINE_HEIGHT_FACTOR: f32 = 1.3;
+
+// What an annotation marks, which decides its color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnnotationKind {
+    Marker,   // a saved scroll marker
+    PauseCue, // the line a pause cue stops at
+}
+
+// A tint behind a range of source lines. Features that mark up parts of the script add
+// annotations instead of painting their own highlights
+#[derive(Debug, Clone)]
+struct Annotation {
+    kind: AnnotationKind,
+    lines: std::ops::RangeInclusive<usize>, // 1-based source lines
+}
+
+// Opacity of annotation tints
+const ANNOTATION_ALPHA: f32 = 0.15;
+
+// Number of earlier versions of the file kept for undoing reloads
+const RELOAD_HISTORY: usize = 5;
+
+// Theme list and selected theme, next to settings.toml
+const THEMES_PATH: &str = "themes.toml";
+
+// Bounds for the content font size
+const MIN_FONT_SIZE: f32 = 8.0;
+const MAX_FONT_SIZE: f32 = 72.0;
+
+// Largest configurable font size step, and the step taken with Shift held
+const MAX_FONT_SIZE_STEP: f32 = 10.0;
+const FONT_SIZE_JUMP: f32 = 5.0;
+
+// Seconds a font size button is held before it repeats, and between repeats
+const HOLD_REPEAT_DELAY: f32 = 0.4;
+const HOLD_REPEAT_INTERVAL: f32 = 0.08;
+
+// Largest indent per heading level, in pixels
+const MAX_HEADING_INDENT: f32 = 100.0;
+
+// Bounds for the interface scale, in physical pixels per point
+const MIN_UI_SCALE: f32 = 0.5;
+const MAX_UI_SCALE: f32 = 3.0;
+
+// Longest wait at the end before looping back to the top, in seconds
+const MAX_LOOP_DELAY: f32 = 60.0;
+
+// Most lines of lead-in to start before the top when looping
+const MAX_LOOP_LEAD_IN: f32 = 20.0;
+
+// Longest wait after a file loads before kiosk mode starts playing, in seconds
+const MAX_KIOSK_START_DELAY: f32 = 60.0;
+
+// Bounds for the metronome tempo, in beats per minute
+const MIN_METRONOME_BPM: f32 = 20.0;
+const MAX_METRONOME_BPM: f32 = 300.0;
+
+// Largest padding on each side of the content, in pixels
+const MAX_CONTENT_PADDING: f32 = 400.0;
+
+// Largest space a run of blank lines can leave, in multiples of the font size
+const MAX_PARAGRAPH_GAP: f32 = 4.0;
+
+// Bounds for the height of a banner image, in points
+const MIN_BANNER_HEIGHT: f32 = 20.0;
+const MAX_BANNER_HEIGHT: f32 = 1000.0;
+
+// Smallest safe area that can be outlined, as a percentage of the content panel
+const MIN_SAFE_AREA: f32 = 10.0;
+
+// Bounds for the code font size relative to the surrounding text
+const MIN_CODE_FONT_SCALE: f32 = 0.5;
+const MAX_CODE_FONT_SCALE: f32 = 2.0;
+
+// Font family used for code, made up of the chosen code font and the default monospace fonts
+const CODE_FONT_FAMILY: &str = "code";
+
+// Bounds for the pause at each heading, in seconds
+const MIN_HEADING_PAUSE: f32 = 0.5;
+const MAX_HEADING_PAUSE: f32 = 10.0;
+
+// Names of the speed preset buttons, in the order of Settings::speed_presets
+const SPEED_PRESET_NAMES: [&str; 3] = ["Slow", "Medium", "Fast"];
+
+// Markdown extensions that can be turned off for scripts that use their characters literally
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct MarkdownExtensions {
+    strikethrough: bool, // ~~text~~
+    table: bool,
+    tasklist: bool, // - [ ] and - [x] items
+    footnotes: bool,
+}
+
+impl Default for MarkdownExtensions {
+    fn default() -> Self {
+        MarkdownExtensions {
+            strikethrough: true,
+            table: true,
+            tasklist: true,
+            footnotes: true,
+        }
+    }
+}
+
+// Space kept clear around the content inside the content panel, in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct ContentPadding {
+    top: f32,
+    bottom: f32,
+    left: f32,
+    right: f32,
+}
+
+impl ContentPadding {
+    fn sides_mut(&mut self) -> [(&'static str, &mut f32); 4] {
+        [
+            ("Top", &mut self.top),
+            ("Bottom", &mut self.bottom),
+            ("Left", &mut self.left),
+            ("Right", &mut self.right),
+        ]
+    }
+}
+
+// Headings whose text matches the pattern, a regular expression, are shown in the current
+// theme's role color of that name instead of their level's color
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct HeadingRole {
+    pattern: String,
+    color: String,
+}
+
+// Where the window was and how big it was when the app last closed, in points
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct WindowGeometry {
+    position: [f32; 2], // top-left of the outer frame
+    size: [f32; 2],     // inner size
+    monitor_size: Option<[f32; 2]>,
+}
+
+// What the file watcher noticed about the open file
+enum WatchEvent {
+    Modified,
+    Deleted,
+}
+
+// Events from a file's watcher thread, which stops when this is dropped
+struct FileWatcher {
+    events: Receiver<WatchEvent>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// Persistent application settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    min_scroll_speed: f32,
+    max_scroll_speed: f32,
+    scroll_speed_step: f32,
+    font_size_step: f32, // pixels added or removed by the font size buttons
+    speed_presets: [f32; 3],
+    speed_unit: SpeedUnit, // unit of all the speeds here and of saved per-file speeds
+    pause_on_focus_loss: bool,
+    resume_on_focus: bool,
+    center_current_line: bool,
+    two_columns: bool, // newspaper-style layout, reading down one column then the next
+    show_clock: bool,
+    end_of_script_sound: bool,
+    metronome: bool, // click at a steady tempo while playing
+    metronome_bpm: f32,
+    metronome_follows_pace: bool, // one click per word at the current speed instead
+    hide_cursor: bool, // hide the mouse cursor while playing once it's been still a while
+    auto_hide_controls: bool, // fade the controls panel out while playing
+    controls_hide_delay: f32, // seconds after playback starts or the pointer leaves
+    controls_reveal_width: f32, // pointer movement this close to the left edge shows them
+    controls_width: f32,
+    controls_collapsed: bool, // folded away to the chevron, leaving the width to the content
+    subpixel_scrolling: bool, // draw text between pixels while playing rather than snapping it
+    fade_in: bool,            // fade content in when a file is loaded or reloaded
+    animate_theme_changes: bool, // blend into a new theme's colors rather than snapping
+    grayscale: bool,          // show the theme's colors as grays of the same brightness
+    state_events: bool,       // print playback changes to stdout as JSON lines
+    show_safe_areas: bool,    // outline the safe areas over the content panel
+    safe_areas: Vec<f32>,     // safe area sizes, as percentages of the content panel
+    show_line_numbers: bool,
+    show_annotations: bool,     // tint lines with markers and pause cues
+    spotlight: bool,            // dim text by its distance from the focus line
+    spotlight_strength: f32,    // how dim the furthest text gets, from 0 to 1
+    highlight_sentence: bool,   // tint the sentence at the focus line
+    lint_markup: bool,          // flag lines with unclosed emphasis, code or strikethrough markers
+    code_font: Option<PathBuf>, // TTF/OTF file for code, instead of egui's monospace font
+    code_font_scale: f32,       // code size relative to the text around it
+    clock_24_hour: bool,
+    persist_markers: bool,
+    resume_files: bool, // reopen files where they were left
+    interpret_html: bool,
+    show_notes: bool, // show <!-- comments --> dimmed instead of hiding them
+    emoji_shortcodes: bool,
+    newlines_as_breaks: bool, // keep each source line on its own line instead of joining them
+    paragraph_gap: f32,       // space left by a run of blank lines, in multiples of the font size
+    banner_max_height: f32,   // tallest a line that's only an image is shown, in points
+    markdown_extensions: MarkdownExtensions,
+    content_padding: ContentPadding,
+    text_alignment: TextAlignment,
+    heading_alignment: TextAlignment,
+    indent_headings: bool, // indent each heading and the text under it by its level
+    number_headings: bool, // show section numbers like "1.2" before headings
+    heading_indent: f32,   // indent per heading level below H1, in pixels
+    scrollbar: ScrollbarMode,
+    end_behavior: EndBehavior,
+    link_action: LinkAction,
+    links_inert_while_playing: bool, // so a stray click can't open a browser mid-read
+    loop_delay: f32,                 // seconds at the end before looping
+    loop_lead_in: f32,               // lines to start before the top when looping
+    kiosk_mode: bool,                // start playing on its own after a file loads, and loop
+    kiosk_start_delay: f32,          // seconds from loading to playing in kiosk mode
+    themed_scrollbar: bool, // color the scrollbar from the theme instead of the window style
+    export_heading_markup: bool, // keep "#" markers in plain text exports instead of uppercasing
+    cue_log_time: CueTime,
+    per_file_settings: bool,
+    default_scroll_speed: f32, // used at startup and for files without saved settings
+    default_font_size: f32,
+    last_directory: Option<PathBuf>, // where the open dialog starts
+    playlist: Vec<PathBuf>,          // scripts queued for a show in several parts
+    playlist_auto_advance: bool,     // open the next script when one reaches its end
+    heading_roles: Vec<HeadingRole>, // checked in order, the first match winning
+    window: Option<WindowGeometry>,
+    ui_scale: Option<f32>, // pixels per point, taken from the display on first run
+    files: BTreeMap<String, FileSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_scroll_speed: 10.0,
+            max_scroll_speed: 500.0,
+            scroll_speed_step: 10.0,
+            font_size_step: 1.0,
+            speed_presets: [30.0, 60.0, 120.0],
+            speed_unit: SpeedUnit::PixelsPerSecond,
+            pause_on_focus_loss: false,
+            resume_on_focus: false,
+            center_current_line: false,
+            two_columns: false,
+            show_clock: false,
+            end_of_script_sound: false,
+            metronome: false,
+            metronome_bpm: 60.0,
+            metronome_follows_pace: false,
+            hide_cursor: false,
+            auto_hide_controls: false,
+            controls_hide_delay: 3.0,
+            controls_reveal_width: 40.0,
+            controls_width: 300.0,
+            controls_collapsed: false,
+            subpixel_scrolling: true,
+            fade_in: false,
+            animate_theme_changes: true,
+            grayscale: false,
+            state_events: false,
+            show_safe_areas: false,
+            safe_areas: vec![90.0, 80.0],
+            show_line_numbers: false,
+            show_annotations: true,
+            spotlight: false,
+            spotlight_strength: 0.6,
+            highlight_sentence: false,
+            lint_markup: false,
+            code_font: None,
+            code_font_scale: 0.9,
+            clock_24_hour: true,
+            persist_markers: false,
+            resume_files: false,
+            interpret_html: true,
+            show_notes: false,
+            emoji_shortcodes: true,
+            newlines_as_breaks: true,
+            paragraph_gap: 1.0,
+            banner_max_height: 200.0,
+            markdown_extensions: MarkdownExtensions::default(),
+            content_padding: ContentPadding::default(),
+            text_alignment: TextAlignment::Left,
+            heading_alignment: TextAlignment::Left,
+            indent_headings: false,
+            number_headings: false,
+            heading_indent: 24.0,
+            scrollbar: ScrollbarMode::Shown,
+            end_behavior: EndBehavior::Stop,
+            link_action: LinkAction::Open,
+            links_inert_while_playing: true,
+            loop_delay: 0.0,
+            loop_lead_in: 0.0,
+            kiosk_mode: false,
+            kiosk_start_delay: 5.0,
+            themed_scrollbar: false,
+            export_heading_markup: false,
+            cue_log_time: CueTime::default(),
+            per_file_settings: false,
+            default_scroll_speed: 50.0,
+            default_font_size: 18.0,
+            last_directory: None,
+            playlist: Vec::new(),
+            playlist_auto_advance: false,
+            heading_roles: Vec::new(),
+            window: None,
+            ui_scale: None,
+            files: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    // Reset any values that would make the controls unusable
+    fn validate(&mut self) {
+        let (default_min, default_max, default_step) = self.speed_unit.default_limits();
+        if self.min_scroll_speed <= 0.0 || self.min_scroll_speed >= self.max_scroll_speed {
+            eprintln!(
+                "Invalid speed bounds {}-{}, using defaults",
+                self.min_scroll_speed, self.max_scroll_speed
+            );
+            self.min_scroll_speed = default_min;
+            self.max_scroll_speed = default_max;
+        }
+        if self.scroll_speed_step <= 0.0 {
+            self.scroll_speed_step = default_step;
+        }
+        if !(self.font_size_step > 0.0 && self.font_size_step <= MAX_FONT_SIZE_STEP) {
+            self.font_size_step = Settings::default().font_size_step;
+        }
+        self.speed_presets = self.speed_presets.map(|preset| self.clamp_speed(preset));
+        self.safe_areas
+            .retain(|percent| (MIN_SAFE_AREA..=100.0).contains(percent));
+        if !(0.0..=MAX_HEADING_INDENT).contains(&self.heading_indent) {
+            self.heading_indent = Settings::default().heading_indent;
+        }
+        for (_, side) in self.content_padding.sides_mut() {
+            if !(0.0..=MAX_CONTENT_PADDING).contains(side) {
+                *side = 0.0;
+            }
+        }
+        if self
+            .ui_scale
+            .is_some_and(|scale| !(MIN_UI_SCALE..=MAX_UI_SCALE).contains(&scale))
+        {
+            self.ui_scale = None;
+        }
+        if !(0.0..=1.0).contains(&self.spotlight_strength) {
+            self.spotlight_strength = Settings::default().spotlight_strength;
+        }
+        if !(0.0..=MAX_CONTROLS_HIDE_DELAY).contains(&self.controls_hide_delay) {
+            self.controls_hide_delay = Settings::default().controls_hide_delay;
+        }
+        if !(MIN_CONTROLS_WIDTH..=MAX_CONTROLS_WIDTH).contains(&self.controls_width) {
+            self.controls_width = Settings::default().controls_width;
+        }
+        if !(0.0..=MAX_CONTROLS_WIDTH).contains(&self.controls_reveal_width) {
+            self.controls_reveal_width = Settings::default().controls_reveal_width;
+        }
+        if !(0.0..=MAX_LOOP_DELAY).contains(&self.loop_delay) {
+            self.loop_delay = Settings::default().loop_delay;
+        }
+        if !(0.0..=MAX_LOOP_LEAD_IN).contains(&self.loop_lead_in) {
+            self.loop_lead_in = Settings::default().loop_lead_in;
+        }
+        if !(0.0..=MAX_KIOSK_START_DELAY).contains(&self.kiosk_start_delay) {
+            self.kiosk_start_delay = Settings::default().kiosk_start_delay;
+        }
+        if !(MIN_METRONOME_BPM..=MAX_METRONOME_BPM).contains(&self.metronome_bpm) {
+            self.metronome_bpm = Settings::default().metronome_bpm;
+        }
+        if !(0.0..=MAX_PARAGRAPH_GAP).contains(&self.paragraph_gap) {
+            self.paragraph_gap = Settings::default().paragraph_gap;
+        }
+        if !(MIN_BANNER_HEIGHT..=MAX_BANNER_HEIGHT).contains(&self.banner_max_height) {
+            self.banner_max_height = Settings::default().banner_max_height;
+        }
+        if !(MIN_CODE_FONT_SCALE..=MAX_CODE_FONT_SCALE).contains(&self.code_font_scale) {
+            self.code_font_scale = Settings::default().code_font_scale;
+        }
+    }
+
+    fn clamp_speed(&self, speed: f32) -> f32 {
+        speed.clamp(self.min_scroll_speed, self.max_scroll_speed)
+    }
 }
 
 // Application state
@@ -26,31 +985,114 @@ struct MarkPrompter {
     current_file: Option<PathBuf>,
     content: String,
     parsed_content: String,
+    blocks: Vec<RenderBlock>, // content split into rows, rebuilt when it changes
+    block_lines: Vec<usize>,  // source line each block starts on
+    annotations: Vec<Annotation>, // tinted line ranges that come from the content
+    lint_lines: Vec<usize>,   // sorted source lines with unclosed markup
+    link_definitions: HashMap<String, String>, // "[ref]: url" lines, by normalized label
+    banners: HashMap<String, Option<egui::TextureHandle>>, // banner images by path, if loaded
+    encoding_warning: Option<String>, // how the open file was decoded, if it wasn't UTF-8
+    word_count: usize,
+    section_words: Vec<usize>, // words before the first heading, then under each heading
+    collapsed_sections: Vec<String>, // headings folded in the outline for the open file
+    line_count: usize,
+    undo_reloads: VecDeque<String>, // content before each recent reload, oldest first
+    redo_reloads: Vec<String>,      // content replaced by undoing a reload
 
     // Scroll control
     scroll_position: f32,
-    scroll_speed: f32, // pixels per second
+    scroll_requested: bool, // push scroll_position to the ScrollArea on the next frame
+    scroll_fraction: Option<f32>, // place to restore, as a fraction of the height, after a relayout
+    content_height: f32,    // total content height from the last frame
+    content_width: f32,     // content panel width from the last frame
+    viewport_height: f32,   // visible height of the content from the last frame
+    scroll_speed: f32,      // in settings.speed_unit
     is_playing: bool,
+    paused_by_focus_loss: bool,
+    end_reached: Option<Instant>, // when playback last stopped at the end, for the notice
+    content_fade: f32,            // opacity of the content, rising from 0 to 1 after a load
+    theme_fade_from: [Color32; 2], // background and text colors a theme change started from
+    theme_fade_to: Option<[Color32; 2]>, // and the theme's own, none until the first frame
+    theme_fade: f32,              // how far the change has got, from 0 to 1
+    metronome_beat: f32, // how far through the current metronome beat playback is, from 0 to 1
+    wheel_speed_delta: f32, // wheel movement not yet making up a whole speed step
+    speed_readout: Option<Instant>, // when the speed was last changed with the wheel
+    font_step_repeat: Option<Instant>, // when a held font size button next repeats
+    reported_playing: bool, // playback state last printed as a state event
+    reported_section: Option<usize>, // heading last printed as a state event
+    last_pointer_activity: Instant,
+    controls_wanted: Instant, // last time the controls panel was in use or asked for
     last_update: Instant,
 
     // Display settings
     font_size: f32,
+    show_raw: bool, // show the markdown source instead of the rendered view
 
     // Feature toggles
     pause_at_headings: bool,
-    auto_restart: bool,
+    reversing: bool,              // scrolling back up in ping-pong mode
+    loop_wait: Option<f32>,       // seconds left at the end before looping
+    lead_in: f32,                 // blank space above the top still to scroll through after looping
+    kiosk_start: Option<Instant>, // when the file kiosk mode is waiting to start playing loaded
     heading_pause_duration: f32,
-    current_heading_pause: Option<f32>,
-    heading_line_indices: Vec<usize>,
+    pause_at_paragraphs: bool,
+    paragraph_pause_duration: f32,
+    current_pause: Option<f32>, // seconds left of a pause at a heading or pause cue
+    heading_pause: bool,        // current_pause is for a heading rather than a cue or paragraph
+    heading_offsets: Vec<f32>,  // top y-offset of each heading within the content
+    pause_cues: Vec<(f32, f32)>, // y-offset and duration in seconds of each pause cue
+    paragraph_offsets: Vec<f32>, // y-offset of each row that follows blank lines
+    row_heights: Vec<f32>,      // last laid-out (or estimated) height of each content row
     last_checked_heading_idx: usize,
+    last_checked_cue_idx: usize,
+    last_checked_paragraph_idx: usize,
+
+    // Markers
+    markers: [Option<f32>; MARKER_COUNT],
+    selected_marker: usize, // 1-based slot used by the marker controls
+    practice_range: bool,   // play only between two markers, ending there instead
+    practice_markers: (usize, usize), // 1-based slots the practice range starts and ends at
 
     // Theme
     current_theme: Theme,
     available_themes: Vec<Theme>,
+    default_theme: String, // saved theme preference, used for files without their own theme
+    theme_override: Option<String>, // from --theme, used this session until another is picked
+    heading_role_patterns: Vec<(regex::Regex, String)>, // settings.heading_roles, compiled
+    theme_status: Option<Result<String, String>>, // result of the last import/export
+    export_status: Option<Result<String, String>>, // result of the last plain text or cue export
+    cue_log: Vec<CueLogEntry>, // headings passed while playing since the last restart
+    cue_log_elapsed: f32,  // seconds of playing since the last restart
+    cue_log_heading_idx: usize, // next heading to log
+    theme_edited: bool,    // current_theme has unsaved edits from the color pickers
+    theme_conflicts: Vec<Theme>, // imported themes whose names are already taken, awaiting a choice
+    font_size_chosen: bool, // the font size was changed with the controls this session
+    code_font_error: Option<String>, // why the chosen code font couldn't be used
+
+    // Persistent settings
+    settings: Settings,
+
+    // Fenced code block highlighting
+    highlighter: CodeHighlighter,
+
+    // Sound effects
+    sounds: Sounds,
 
     // File watcher
-    _file_watcher_tx: Option<Sender<()>>,
-    file_watcher_rx: Option<Receiver<()>>,
+    file_watcher: Option<FileWatcher>,
+    themes_watcher: Option<FileWatcher>, // changes to themes.toml made elsewhere
+    themes_error: Option<String>,        // why themes.toml couldn't be reloaded
+    file_missing: bool,                  // the open file was deleted or moved away
+    resumed: bool, // the open file was restored to where it was left, until dismissed
+    calibration: Option<Calibration>,
+    target_minutes: f32, // time to finish the script in, for the suggested speed
+    egui_ctx: egui::Context, // lets the watcher wake the UI when it's idle
+
+    // Window geometry
+    window_geometry: Option<WindowGeometry>, // latest normal (not maximized) geometry
+    window_check_pending: bool, // a restored position still needs checking against the monitor
+    ui_scale_applied: bool,     // settings.ui_scale has been passed on to egui
+    ui_scale_edit: Option<f32>, // UI scale being dragged, applied when let go
 }
 
 impl Default for Theme {
@@ -67,6 +1109,14 @@ impl Default for Theme {
                 [170, 100, 60],  // H5
                 [150, 80, 50],   // H6
             ],
+            heading_decoration: HeadingDecoration::None,
+            inline_code_background: None,
+            inline_code_color: None,
+            default_font_size: None,
+            accent_color: None,
+            heading_prefixes: Vec::new(),
+            role_colors: BTreeMap::new(),
+            alert_colors: BTreeMap::new(),
         }
     }
 }
@@ -77,30 +1127,100 @@ impl Default for MarkPrompter {
             current_file: None,
             content: String::new(),
             parsed_content: String::new(),
+            blocks: Vec::new(),
+            block_lines: Vec::new(),
+            annotations: Vec::new(),
+            lint_lines: Vec::new(),
+            link_definitions: HashMap::new(),
+            banners: HashMap::new(),
+            encoding_warning: None,
+            word_count: 0,
+            section_words: Vec::new(),
+            line_count: 0,
+            undo_reloads: VecDeque::new(),
+            redo_reloads: Vec::new(),
             scroll_position: 0.0,
+            scroll_requested: false,
+            scroll_fraction: None,
+            content_height: 0.0,
+            content_width: 0.0,
+            viewport_height: 0.0,
             scroll_speed: 50.0,
             is_playing: false,
+            paused_by_focus_loss: false,
+            end_reached: None,
+            content_fade: 1.0,
+            theme_fade_from: [Color32::BLACK; 2],
+            theme_fade_to: None,
+            theme_fade: 1.0,
+            metronome_beat: 0.0,
+            wheel_speed_delta: 0.0,
+            speed_readout: None,
+            font_step_repeat: None,
+            reported_playing: false,
+            reported_section: None,
+            last_pointer_activity: Instant::now(),
+            controls_wanted: Instant::now(),
             last_update: Instant::now(),
             font_size: 18.0,
+            show_raw: false,
             pause_at_headings: false,
-            auto_restart: false,
+            reversing: false,
+            loop_wait: None,
+            lead_in: 0.0,
+            kiosk_start: None,
             heading_pause_duration: 2.0,
-            current_heading_pause: None,
-            heading_line_indices: Vec::new(),
+            pause_at_paragraphs: false,
+            paragraph_pause_duration: 1.0,
+            current_pause: None,
+            heading_pause: false,
+            heading_offsets: Vec::new(),
+            pause_cues: Vec::new(),
+            paragraph_offsets: Vec::new(),
+            row_heights: Vec::new(),
             last_checked_heading_idx: 0,
+            last_checked_paragraph_idx: 0,
+            last_checked_cue_idx: 0,
+            markers: [None; MARKER_COUNT],
+            collapsed_sections: Vec::new(),
+            selected_marker: 1,
+            practice_range: false,
+            practice_markers: (1, 2),
             current_theme: Theme::default(),
             available_themes: vec![Theme::default()],
-            _file_watcher_tx: None,
-            file_watcher_rx: None,
+            default_theme: Theme::default().name,
+            theme_override: None,
+            heading_role_patterns: Vec::new(),
+            theme_status: None,
+            export_status: None,
+            cue_log: Vec::new(),
+            cue_log_elapsed: 0.0,
+            cue_log_heading_idx: 0,
+            theme_edited: false,
+            theme_conflicts: Vec::new(),
+            font_size_chosen: false,
+            code_font_error: None,
+            settings: Settings::default(),
+            highlighter: CodeHighlighter::default(),
+            sounds: Sounds::default(),
+            file_watcher: None,
+            themes_watcher: None,
+            themes_error: None,
+            file_missing: false,
+            resumed: false,
+            calibration: None,
+            target_minutes: 3.0,
+            egui_ctx: egui::Context::default(),
+            window_geometry: None,
+            window_check_pending: false,
+            ui_scale_applied: false,
+            ui_scale_edit: None,
         }
     }
 }
 
 impl MarkPrompter {
-    fn new(cc: &CreationContext) -> Self {
-        // Initialize material icons
-        egui_material_icons::initialize(&cc.egui_ctx);
-
+    fn new(cc: &CreationContext, initial_file: Option<PathBuf>, theme: Option<String>) -> Self {
         // Configure fonts
         let mut style = (*cc.egui_ctx.style()).clone();
         style.text_styles = [
@@ -114,10 +1234,13 @@ impl MarkPrompter {
         cc.egui_ctx.set_style(style);
 
         // Load themes from config file if it exists
-        let mut app = Self::default();
+        let mut app = Self {
+            egui_ctx: cc.egui_ctx.clone(),
+            ..Self::default()
+        };
         match load_themes_and_preference() {
             Ok((themes, saved_theme)) => {
-                println!("Themes loaded successfully: {} themes", themes.len());
+                eprintln!("Themes loaded successfully: {} themes", themes.len());
                 app.available_themes = themes;
 
                 // Load saved theme preference
@@ -129,7 +1252,7 @@ impl MarkPrompter {
                         .find(|t| t.name == saved_theme_name.trim())
                         .cloned()
                     {
-                        println!("Restored saved theme: {}", saved_theme.name);
+                        eprintln!("Restored saved theme: {}", saved_theme.name);
                         app.current_theme = saved_theme;
                     } else if !app.available_themes.is_empty() {
                         // Fallback to first theme if saved theme not found
@@ -141,16 +1264,107 @@ impl MarkPrompter {
                 }
             }
             Err(e) => {
-                println!("Error loading themes: {}", e);
+                eprintln!("Error loading themes: {}", e);
             }
         }
+        app.themes_watcher = Some(watch_file(PathBuf::from(THEMES_PATH), cc.egui_ctx.clone()));
 
-        app
-    }
+        // Load application settings
+        match load_settings() {
+            Ok(settings) => {
+                app.settings = settings;
+            }
+            Err(e) => {
+                eprintln!("Error loading settings: {}", e);
+            }
+        }
+        app.settings.validate();
+        app.scroll_speed = app.settings.clamp_speed(app.settings.default_scroll_speed);
+        app.font_size = app
+            .settings
+            .default_font_size
+            .clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        app.default_theme = app.current_theme.name.clone();
 
-    // Parse and render inline markdown formatting
-    fn render_formatted_text(
-        &self,
+        // A theme named on the command line is used for this session without being saved
+        if let Some(name) = theme {
+            match app.available_themes.iter().find(|t| t.name == name.trim()) {
+                Some(theme) => {
+                    app.current_theme = theme.clone();
+                    app.theme_override = Some(theme.name.clone());
+                }
+                None => eprintln!(
+                    "Theme not found: {}, using {}",
+                    name, app.current_theme.name
+                ),
+            }
+        }
+        app.window_check_pending = app.settings.window.is_some();
+        app.apply_fonts();
+        app.compile_heading_roles();
+
+        // Open a script passed on the command line, falling back to the empty state
+        if let Some(path) = initial_file {
+            if path.is_file() && is_markdown_path(&path) {
+                app.load_file(path);
+            } else {
+                eprintln!("Not a markdown file: {}", path.display());
+            }
+        }
+
+        app
+    }
+
+    fn save_settings(&self) {
+        if let Err(e) = save_settings(&self.settings) {
+            eprintln!("Failed to save settings: {}", e);
+        }
+    }
+
+    // Compile the heading role patterns, leaving out any that aren't valid
+    fn compile_heading_roles(&mut self) {
+        self.heading_role_patterns = self
+            .settings
+            .heading_roles
+            .iter()
+            .filter_map(|role| match regex::Regex::new(&role.pattern) {
+                Ok(pattern) => Some((pattern, role.color.clone())),
+                Err(e) => {
+                    eprintln!("Ignoring heading role \"{}\": {}", role.pattern, e);
+                    None
+                }
+            })
+            .collect();
+    }
+
+    // Install the fonts, falling back to the default code font if the chosen one can't be used
+    fn apply_fonts(&mut self) {
+        self.code_font_error = install_fonts(&self.egui_ctx, self.settings.code_font.as_deref())
+            .err()
+            .map(|e| {
+                eprintln!("Failed to load code font: {}", e);
+                format!("Couldn't load the code font: {}", e)
+            });
+    }
+
+    fn set_code_font(&mut self, path: Option<PathBuf>) {
+        self.preserve_scroll_fraction();
+        self.settings.code_font = path;
+        self.apply_fonts();
+        self.save_settings();
+    }
+
+    // Font for inline code and code blocks in text of the given size
+    fn code_font(&self, base_size: f32) -> egui::FontId {
+        egui::FontId::new(
+            base_size * self.settings.code_font_scale,
+            egui::FontFamily::Name(CODE_FONT_FAMILY.into()),
+        )
+    }
+
+    // Parse and render inline markdown formatting
+    fn render_formatted_text(
+        &self,
         ui: &mut egui::Ui,
         text: &str,
         base_color: Color32,
@@ -161,6 +1375,7 @@ impl MarkPrompter {
         let mut job = LayoutJob::default();
         let mut chars = text.chars().peekable();
         let mut current_text = String::new();
+        let mut links = Vec::new(); // character range in the job and URL of each link
 
         while let Some(ch) = chars.next() {
             match ch {
@@ -273,6 +1488,90 @@ impl MarkPrompter {
                         current_text.push(ch);
                     }
                 }
+                '~' if self.settings.markdown_extensions.strikethrough
+                    && chars.peek() == Some(&'~') =>
+                {
+                    chars.next(); // consume second marker
+
+                    // Find closing markers
+                    let mut content = String::new();
+                    let mut found_closing = false;
+                    while let Some(inner_ch) = chars.next() {
+                        if inner_ch == '~' && chars.peek() == Some(&'~') {
+                            chars.next(); // consume second closing marker
+                            found_closing = true;
+                            break;
+                        }
+                        content.push(inner_ch);
+                    }
+
+                    if found_closing {
+                        if !current_text.is_empty() {
+                            job.append(
+                                &current_text,
+                                0.0,
+                                TextFormat {
+                                    font_id: FontId::proportional(base_size),
+                                    color: base_color,
+                                    ..Default::default()
+                                },
+                            );
+                            current_text.clear();
+                        }
+                        job.append(
+                            &content,
+                            0.0,
+                            TextFormat {
+                                font_id: FontId::proportional(base_size),
+                                color: base_color,
+                                strikethrough: egui::Stroke::new(base_size / 12.0, base_color),
+                                ..Default::default()
+                            },
+                        );
+                    } else {
+                        // No closing found, treat as normal text
+                        current_text.push_str("~~");
+                        current_text.push_str(&content);
+                    }
+                }
+                '[' => {
+                    // Links show their text underlined in the accent color. References to
+                    // undefined links are left as written
+                    let rest: String = chars.clone().collect();
+                    match parse_link(&rest, &self.link_definitions) {
+                        Some((link_text, url, used)) => {
+                            if !current_text.is_empty() {
+                                job.append(
+                                    &current_text,
+                                    0.0,
+                                    TextFormat {
+                                        font_id: FontId::proportional(base_size),
+                                        color: base_color,
+                                        ..Default::default()
+                                    },
+                                );
+                                current_text.clear();
+                            }
+                            let start = job.text.chars().count();
+                            links.push((start..start + link_text.chars().count(), url));
+                            job.append(
+                                link_text,
+                                0.0,
+                                TextFormat {
+                                    font_id: FontId::proportional(base_size),
+                                    color: base_color,
+                                    underline: egui::Stroke::new(
+                                        base_size / 14.0,
+                                        self.shown_color(self.current_theme.accent_color()),
+                                    ),
+                                    ..Default::default()
+                                },
+                            );
+                            chars.nth(rest[..used].chars().count() - 1);
+                        }
+                        None => current_text.push('['),
+                    }
+                }
                 '`' => {
                     // Code formatting
                     if !current_text.is_empty() {
@@ -305,9 +1604,11 @@ impl MarkPrompter {
                             &content,
                             0.0,
                             TextFormat {
-                                font_id: FontId::monospace(base_size * 0.9),
-                                color: base_color,
-                                background: Color32::from_rgba_premultiplied(80, 80, 80, 40),
+                                font_id: self.code_font(base_size),
+                                color: self
+                                    .shown_color(self.current_theme.inline_code_color(base_color)),
+                                background: self
+                                    .shown_color(self.current_theme.inline_code_background()),
                                 ..Default::default()
                             },
                         );
@@ -335,358 +1636,5504 @@ impl MarkPrompter {
             );
         }
 
-        ui.label(job);
-    }
+        if links.is_empty() && !self.settings.highlight_sentence {
+            ui.label(job);
+            return;
+        }
 
-    fn open_file(&mut self) {
-        if let Some(path) = FileDialog::new()
-            .add_filter("Markdown", &["md", "markdown"])
-            .pick_file()
-        {
-            self.load_file(path);
+        // With links, the text is laid out here so the character under the pointer can be
+        // matched to the link it's part of, and with sentence highlighting so the sentence at
+        // the focus line can be found
+        let (pos, galley, response) = egui::Label::new(job)
+            .sense(egui::Sense::click())
+            .layout_in_ui(ui);
+        if self.settings.highlight_sentence {
+            self.paint_focus_sentence(ui, pos, &galley);
+        }
+        ui.painter().galley(pos, galley.clone(), base_color);
+        let hovered_link = response.hover_pos().and_then(|pointer| {
+            let index = galley.cursor_from_pos(pointer - pos).index;
+            links
+                .iter()
+                .find(|(range, _)| range.contains(&index))
+                .map(|(_, url)| url)
+        });
+        if let Some(url) = hovered_link.filter(|_| self.links_clickable()) {
+            ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+            if response.on_hover_text(url.as_str()).clicked() {
+                match self.settings.link_action {
+                    LinkAction::Open => ui.ctx().open_url(egui::OpenUrl::new_tab(url)),
+                    LinkAction::Copy => ui.ctx().copy_text(url.clone()),
+                    LinkAction::Ignore => {}
+                }
+            }
         }
     }
 
-    fn load_file(&mut self, path: PathBuf) {
-        match fs::read_to_string(&path) {
-            Ok(content) => {
-                self.content = content;
-                self.parse_markdown();
-                self.current_file = Some(path.clone());
-                self.scroll_position = 0.0;
-                self.last_checked_heading_idx = 0;
-
-                // Set up file watcher
-                let (tx, rx) = channel();
-                self.file_watcher_rx = Some(rx);
-
-                let path_clone = path.clone();
-                let watcher_tx = tx.clone();
-                thread::spawn(move || {
-                    let mut last_modified = fs::metadata(&path_clone)
-                        .ok()
-                        .and_then(|m| m.modified().ok());
-
-                    loop {
-                        thread::sleep(Duration::from_secs(1));
-
-                        if let Ok(metadata) = fs::metadata(&path_clone) {
-                            if let Ok(modified) = metadata.modified() {
-                                if let Some(last) = last_modified {
-                                    if modified > last {
-                                        let _ = watcher_tx.send(());
-                                        last_modified = Some(modified);
-                                    }
-                                } else {
-                                    last_modified = Some(modified);
-                                }
-                            }
-                        }
-                    }
-                });
+    // Tint behind the sentence of a laid out text that's at the focus line, if it's there
+    fn paint_focus_sentence(&self, ui: &egui::Ui, pos: egui::Pos2, galley: &egui::Galley) {
+        let focus = self.focus_y(ui.clip_rect()) - pos.y;
+        if !(galley.rect.top()..galley.rect.bottom()).contains(&focus) {
+            return;
+        }
+        let index = galley
+            .cursor_from_pos(egui::vec2(galley.rect.center().x, focus))
+            .index;
+        let sentence = sentence_around(galley.text(), index);
+        let color = self
+            .shown_color(self.current_theme.accent_color())
+            .gamma_multiply(SENTENCE_HIGHLIGHT_ALPHA);
 
-                self._file_watcher_tx = Some(tx);
-            }
-            Err(e) => {
-                eprintln!("Error loading file: {}", e);
+        // One rectangle for the part of the sentence on each row it runs across
+        let mut row_start = 0;
+        for row in &galley.rows {
+            let row_end = row_start + row.char_count_including_newline();
+            let start = sentence.start.max(row_start);
+            let end = sentence
+                .end
+                .min(row_start + row.char_count_excluding_newline());
+            if start < end {
+                let left = row.pos.x + row.x_offset(start - row_start);
+                let right = row.pos.x + row.x_offset(end - row_start);
+                let rect = egui::Rect::from_x_y_ranges(left..=right, row.rect().y_range());
+                ui.painter()
+                    .rect_filled(rect.translate(pos.to_vec2()).expand(2.0), 4.0, color);
             }
+            row_start = row_end;
         }
     }
 
-    fn parse_markdown(&mut self) {
-        let mut options = ComrakOptions::default();
-        options.extension.strikethrough = true;
-        options.extension.table = true;
-        options.extension.tasklist = true;
-        options.extension.footnotes = true;
-
-        self.parsed_content = markdown_to_html(&self.content, &options);
-
-        // Extract heading positions for pause-at-headings feature if enabled
-        if self.pause_at_headings {
-            self.extract_heading_positions();
-        }
+    // Links do nothing when set to, and while playing unless they're set to work then too
+    fn links_clickable(&self) -> bool {
+        self.settings.link_action != LinkAction::Ignore
+            && !(self.is_playing && self.settings.links_inert_while_playing)
     }
 
-    fn extract_heading_positions(&mut self) {
-        // Simple approach: check each line for markdown heading markers
-        let lines: Vec<&str> = self.content.lines().collect();
-        let mut heading_line_indices = Vec::new();
+    // Render a heading in its level's color and size, returning the response for its text
+    fn render_heading(&self, ui: &mut egui::Ui, level: usize, text: &str) -> egui::Response {
+        // It's a heading - use the appropriate heading color
+        let idx = level - 1;
+        let heading_color = self.heading_color(level, text);
 
-        for (i, line) in lines.iter().enumerate() {
-            if line.starts_with("# ")
-                || line.starts_with("## ")
-                || line.starts_with("### ")
-                || line.starts_with("#### ")
-                || line.starts_with("##### ")
-                || line.starts_with("###### ")
-            {
-                heading_line_indices.push(i);
-            }
-        }
+        // Adjust font size based on heading level
+        let heading_size = self.font_size * HEADING_SIZE_MULTIPLIERS[idx];
+        ui.style_mut()
+            .text_styles
+            .get_mut(&egui::TextStyle::Body)
+            .unwrap()
+            .size = heading_size;
 
-        // We'll use this information in the update_scroll method
-        self.heading_line_indices = heading_line_indices;
-    }
+        let text = if self.settings.interpret_html {
+            convert_inline_html(text, false)
+        } else {
+            text.to_string()
+        };
+        let text = if self.settings.emoji_shortcodes {
+            expand_shortcodes(&text)
+        } else {
+            text
+        };
+        let text = match self.current_theme.heading_prefixes.get(idx) {
+            Some(prefix) if !prefix.is_empty() => format!("{}{}", prefix, text),
+            _ => text,
+        };
 
-    fn check_file_updates(&mut self) {
-        if let Some(rx) = &self.file_watcher_rx {
-            if rx.try_recv().is_ok() {
-                if let Some(path) = &self.current_file {
-                    if let Ok(content) = fs::read_to_string(path) {
-                        self.content = content;
-                        self.parse_markdown();
-                    }
-                }
-            }
-        }
-    }
+        // Leave room on the left for an accent bar so it isn't clipped at the panel edge
+        let decoration = self.current_theme.heading_decoration;
+        let thickness = HEADING_DECORATION_THICKNESS[idx];
+        let left_margin = if decoration == HeadingDecoration::AccentBar {
+            thickness + HEADING_DECORATION_GAP
+        } else {
+            0.0
+        };
 
-    fn update_scroll(&mut self, dt: f32) {
-        if !self.is_playing {
-            return;
-        }
+        let response = egui::Frame::NONE
+            .inner_margin(egui::Margin {
+                left: left_margin.round() as i8,
+                ..Default::default()
+            })
+            .show(ui, |ui| {
+                ui.with_layout(self.settings.heading_alignment.layout(), |ui| {
+                    ui.colored_label(heading_color, text)
+                })
+                .inner
+            })
+            .inner;
 
-        // Handle heading pause if enabled
-        if let Some(remaining) = self.current_heading_pause {
-            if remaining > 0.0 {
-                self.current_heading_pause = Some(remaining - dt);
-                return;
-            } else {
-                self.current_heading_pause = None;
+        let rect = response.rect;
+        match decoration {
+            HeadingDecoration::None => {}
+            HeadingDecoration::Underline => {
+                let top = rect.bottom() + 1.0;
+                ui.painter().rect_filled(
+                    egui::Rect::from_x_y_ranges(rect.x_range(), top..=top + thickness),
+                    0.0,
+                    heading_color,
+                );
+            }
+            HeadingDecoration::AccentBar => {
+                let right = rect.left() - HEADING_DECORATION_GAP;
+                ui.painter().rect_filled(
+                    egui::Rect::from_x_y_ranges(right - thickness..=right, rect.y_range()),
+                    0.0,
+                    heading_color,
+                );
             }
         }
 
-        // Calculate new scroll position
-        self.scroll_position += self.scroll_speed * dt;
+        // Reset font size to default
+        ui.style_mut()
+            .text_styles
+            .get_mut(&egui::TextStyle::Body)
+            .unwrap()
+            .size = self.font_size;
 
-        // Check if we should pause at a heading
-        if self.pause_at_headings && !self.heading_line_indices.is_empty() {
-            // Calculate approximate line based on scroll position and font size
-            let approximate_line = (self.scroll_position / (self.font_size * 1.5)) as usize;
+        response
+    }
 
-            // Check if we're approaching a heading
-            for (idx, &heading_line) in self
-                .heading_line_indices
-                .iter()
-                .enumerate()
-                .skip(self.last_checked_heading_idx)
-            {
-                // If we've scrolled past this heading
-                if approximate_line >= heading_line && idx >= self.last_checked_heading_idx {
-                    // Pause scrolling for the specified duration
-                    self.current_heading_pause = Some(self.heading_pause_duration);
-                    self.last_checked_heading_idx = idx + 1;
-                    break;
-                }
-            }
+    // The line being read: the middle of the viewport when centering it, otherwise the top
+    fn focus_y(&self, viewport: egui::Rect) -> f32 {
+        if self.settings.center_current_line {
+            viewport.center().y
+        } else {
+            viewport.top()
         }
     }
-}
 
-impl App for MarkPrompter {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.check_file_updates();
+    // Render the markdown view. Only rows near the visible part of the enclosing ScrollArea
+    // are laid out; the rest are stood in for by empty space of their last known height, so
+    // long scripts stay fast. Returns the number of rows that were laid out. Only a render that
+    // records its layout updates the row heights and offsets playback uses
+    fn render_markdown(
+        &mut self,
+        ui: &mut egui::Ui,
+        text_color: Color32,
+        record_layout: bool,
+    ) -> usize {
+        // Heading offsets are measured relative to the top of the content so they line up
+        // with scroll_position. Skipped rows use the same heights as the space left for
+        // them, so offsets stay consistent with the layout
+        let content_top = ui.cursor().top();
+        let visible = ui.clip_rect().expand2(egui::vec2(0.0, OFFSCREEN_BUFFER));
 
-        // Set background color from theme
-        let bg_color = Color32::from_rgb(
-            self.current_theme.background_color[0],
-            self.current_theme.background_color[1],
-            self.current_theme.background_color[2],
-        );
+        // The spotlight is brightest at the focus line, fading over the height it reads across
+        let viewport = ui.clip_rect();
+        let focus = self.focus_y(viewport);
+        let falloff = if self.settings.center_current_line {
+            viewport.height() / 2.0
+        } else {
+            viewport.height()
+        };
+        let metrics = LayoutMetrics {
+            font_size: self.font_size,
+            min_row_height: ui.spacing().interact_size.y,
+            paragraph_gap: self.settings.paragraph_gap,
+            heading_levels: self.current_theme.heading_colors.len(),
+        };
+        let mut heading_idx = 0;
+        let mut section_level = 1; // level of the heading the current row falls under
+        let mut row_heights = Vec::with_capacity(self.blocks.len());
+        let mut laid_out = 0;
 
-        let mut style = (*ctx.style()).clone();
-        style.visuals.panel_fill = bg_color;
-        style.visuals.window_fill = bg_color;
-        ctx.set_style(style);
+        // Optional gutter with the source line number of each row, wide enough for the last line,
+        let number_font = egui::FontId::monospace(self.font_size * 0.7);
+        let number_color = text_color.gamma_multiply(0.4);
+        let gutter_left = ui.cursor().left();
+        // and with room for the unclosed markup warning
+        let number_width = if self.settings.show_line_numbers {
+            let digits = self.line_count.max(1).to_string().len() as f32;
+            digits * ui.fonts(|f| f.glyph_width(&number_font, '0'))
+        } else {
+            0.0
+        };
+        let lint_width = if self.settings.lint_markup {
+            number_font.size * 1.5
+        } else {
+            0.0
+        };
+        let gutter_width = if number_width + lint_width > 0.0 {
+            number_width + lint_width + LINE_NUMBER_GAP
+        } else {
+            0.0
+        };
+        let warn_color = ui.visuals().warn_fg_color;
+        let content_rect = ui
+            .available_rect_before_wrap()
+            .with_min_x(gutter_left + gutter_width);
 
-        let text_color_z = Color32::from_rgb(
-            self.current_theme.heading_colors[0][0],
-            self.current_theme.heading_colors[0][1],
-            self.current_theme.heading_colors[0][2],
-        );
+        let annotations: Vec<Annotation> = if self.settings.show_annotations {
+            self.annotations
+                .iter()
+                .cloned()
+                .chain(self.marker_annotations())
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            // Use columns with custom width ratio - give more space to controls panel
-            ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
-                ui.allocate_ui_with_layout(
-                    egui::vec2(300.0, ui.available_height()),
-                    egui::Layout::top_down(egui::Align::LEFT),
-                    |ui| {
-                        // Left column - Controls panel
-                        ui.colored_label(text_color_z, RichText::new("MarkPrompter").size(32.0));
+        ui.scope_builder(egui::UiBuilder::new().max_rect(content_rect), |ui| {
+            // A full-width column lets labels be aligned within it
+            egui::Grid::new("markdown_content")
+                .num_columns(1)
+                .min_col_width(ui.available_width())
+                .spacing([0.0, ROW_SPACING])
+                .min_row_height(0.0)
+                .striped(false)
+                .show(ui, |ui| {
+                    let mut top = content_top;
+                    let mut skipped = 0.0;
+                    for (index, (block, line)) in
+                        self.blocks.iter().zip(&self.block_lines).enumerate()
+                    {
+                        // Pause cues take up no space and aren't shown
+                        if let RenderBlock::Pause(_) = block {
+                            row_heights.push(0.0);
+                            continue;
+                        }
+
+                        let is_gap = matches!(block, RenderBlock::Gap);
+                        let height =
+                            metrics.row_height(block, self.row_heights.get(index).copied());
+                        if let RenderBlock::Heading { level, .. } = block {
+                            if self.is_heading(block) {
+                                section_level = *level;
+                            }
+                        }
+                        let indent = if self.settings.indent_headings {
+                            (section_level - 1) as f32 * self.settings.heading_indent
+                        } else {
+                            0.0
+                        };
+                        let highlight = match block {
+                            RenderBlock::Heading { level, text } if self.is_heading(block) => {
+                                let strength =
+                                    self.heading_highlight(heading_idx, top - content_top);
+                                heading_idx += 1;
+                                Some((strength, self.heading_color(*level, text)))
+                            }
+                            _ => None,
+                        };
 
-                        ui.add_space(10.0);
+                        if top + height < visible.top() || top > visible.bottom() {
+                            skipped += height + ROW_SPACING;
+                            row_heights.push(height);
+                            top += height + ROW_SPACING;
+                            continue;
+                        }
 
-                        // File controls
-                        if ui
-                            .add_sized(
-                                [80.0, 80.0],
-                                egui::Button::new(
-                                    egui::RichText::new(format!("{} ", ICON_FOLDER_OPEN))
-                                        .size(28.0),
+                        if skipped > 0.0 {
+                            ui.allocate_space(egui::vec2(
+                                ui.available_width(),
+                                skipped - ROW_SPACING,
+                            ));
+                            ui.end_row();
+                            skipped = 0.0;
+                        }
+
+                        // Gaps are exactly their setting's height, so rows have no minimum
+                        // height in the grid and text rows set their own
+                        if is_gap {
+                            ui.allocate_space(egui::vec2(ui.available_width(), height));
+                            ui.end_row();
+                            row_heights.push(height);
+                            top += height + ROW_SPACING;
+                            continue;
+                        }
+                        let tint = ui.painter().add(egui::Shape::Noop);
+                        let background = ui.painter().add(egui::Shape::Noop);
+                        let rect = ui
+                            .scope(|ui| {
+                                ui.set_min_height(metrics.min_row_height);
+                                if self.settings.spotlight {
+                                    ui.multiply_opacity(spotlight_opacity(
+                                        (top + height / 2.0 - focus).abs() / falloff,
+                                        self.settings.spotlight_strength,
+                                    ));
+                                }
+                                if indent > 0.0 {
+                                    let available = ui.available_rect_before_wrap();
+                                    let indented = egui::UiBuilder::new()
+                                        .max_rect(available.with_min_x(available.left() + indent))
+                                        .layout(egui::Layout::top_down(egui::Align::Min));
+                                    ui.scope_builder(indented, |ui| {
+                                        self.render_block(ui, block, text_color);
+                                    });
+                                } else {
+                                    self.render_block(ui, block, text_color);
+                                }
+                            })
+                            .response
+                            .rect;
+                        if let Some((strength, color)) =
+                            highlight.filter(|(strength, _)| *strength > 0.0)
+                        {
+                            ui.painter().set(
+                                background,
+                                egui::Shape::rect_filled(
+                                    rect.expand(2.0),
+                                    4.0,
+                                    color.gamma_multiply(HEADING_HIGHLIGHT_ALPHA * strength),
                                 ),
-                            )
-                            .clicked()
+                            );
+                        }
+                        // Annotations cover the whole width of every source line in the row
+                        let last_line = self
+                            .block_lines
+                            .get(index + 1)
+                            .map_or(*line, |next| (*next).max(*line + 1) - 1);
+                        let row_rect =
+                            egui::Rect::from_x_y_ranges(content_rect.x_range(), rect.y_range())
+                                .expand2(egui::vec2(0.0, ROW_SPACING / 2.0));
+                        let tints: Vec<egui::Shape> = annotations
+                            .iter()
+                            .filter(|a| *a.lines.start() <= last_line && *a.lines.end() >= *line)
+                            .map(|a| {
+                                egui::Shape::rect_filled(
+                                    row_rect,
+                                    0.0,
+                                    self.annotation_color(a.kind),
+                                )
+                            })
+                            .collect();
+                        if !tints.is_empty() {
+                            ui.painter().set(tint, egui::Shape::Vec(tints));
+                        }
+                        if self.settings.show_line_numbers {
+                            ui.painter().text(
+                                egui::pos2(
+                                    gutter_left + gutter_width - LINE_NUMBER_GAP,
+                                    rect.top(),
+                                ),
+                                egui::Align2::RIGHT_TOP,
+                                line.to_string(),
+                                number_font.clone(),
+                                number_color,
+                            );
+                        }
+                        if self.settings.lint_markup && self.lint_lines.binary_search(line).is_ok()
                         {
-                            self.open_file();
+                            ui.painter().text(
+                                egui::pos2(gutter_left, rect.top()),
+                                egui::Align2::LEFT_TOP,
+                                ICON_WARNING,
+                                egui::FontId::proportional(number_font.size),
+                                warn_color,
+                            );
                         }
+                        let height = rect.height().max(metrics.min_row_height);
+                        ui.end_row();
+                        row_heights.push(height);
+                        top += height + ROW_SPACING;
+                        laid_out += 1;
+                    }
+                    if skipped > 0.0 {
+                        ui.allocate_space(egui::vec2(ui.available_width(), skipped - ROW_SPACING));
+                        ui.end_row();
+                    }
+                });
+        });
 
-                        ui.add_space(20.0);
-                        ui.separator();
-                        ui.add_space(10.0);
+        if !record_layout {
+            return laid_out;
+        }
+        // Offsets come from the heights just used, so they match what was drawn
+        let layout = layout_blocks(&self.blocks, &row_heights, &metrics);
+        self.heading_offsets = layout.heading_offsets;
+        self.pause_cues = layout.pause_cues;
+        self.paragraph_offsets = layout.paragraph_offsets;
+        self.row_heights = layout.heights;
+        laid_out
+    }
 
-                        // Playback controls
-                        ui.heading("Playback");
-                        ui.add_space(5.0);
+    // How strongly to highlight the heading with this index, from 0 to 1: rising as a pause
+    // at it approaches, and held while it's pausing
+    fn heading_highlight(&self, heading_idx: usize, offset: f32) -> f32 {
+        if !self.pause_at_headings {
+            return 0.0;
+        }
+        if self.current_pause.is_some() && self.heading_pause {
+            return if heading_idx + 1 == self.last_checked_heading_idx {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let distance = offset - self.scroll_position;
+        if self.is_playing
+            && heading_idx == self.last_checked_heading_idx
+            && (0.0..HEADING_APPROACH_DISTANCE).contains(&distance)
+        {
+            1.0 - distance / HEADING_APPROACH_DISTANCE
+        } else {
+            0.0
+        }
+    }
 
-                        ui.horizontal(|ui| {
-                            let play_pause_text = if self.is_playing {
-                                egui::RichText::new(ICON_PAUSE).size(48.0)
-                            } else {
-                                egui::RichText::new(ICON_PLAY_ARROW).size(48.0)
-                            };
+    fn annotation_color(&self, kind: AnnotationKind) -> Color32 {
+        let color = match kind {
+            AnnotationKind::Marker => self.current_theme.accent_color(),
+            AnnotationKind::PauseCue => Color32::from_rgb(
+                self.current_theme.text_color[0],
+                self.current_theme.text_color[1],
+                self.current_theme.text_color[2],
+            ),
+        };
+        self.shown_color(color).gamma_multiply(ANNOTATION_ALPHA)
+    }
 
-                            if ui
-                                .add_sized([80.0, 80.0], egui::Button::new(play_pause_text))
-                                .clicked()
-                            {
-                                self.is_playing = !self.is_playing;
-                                self.last_update = Instant::now();
-                            }
+    // Annotate the line at the top of the screen for each saved marker
+    fn marker_annotations(&self) -> Vec<Annotation> {
+        self.markers
+            .iter()
+            .flatten()
+            .filter_map(|&offset| self.line_at_offset(offset))
+            .map(|line| Annotation {
+                kind: AnnotationKind::Marker,
+                lines: line..=line,
+            })
+            .collect()
+    }
 
-                            if ui
-                                .add_sized(
-                                    [80.0, 80.0],
-                                    egui::Button::new(
-                                        egui::RichText::new(ICON_SKIP_PREVIOUS).size(48.0),
-                                    ),
-                                )
-                                .clicked()
-                            {
-                                self.scroll_position = 0.0;
-                                self.last_checked_heading_idx = 0;
-                            }
-                        });
+    // Source line of the row at an offset within the content, going by the last layout
+    fn line_at_offset(&self, offset: f32) -> Option<usize> {
+        let mut top = 0.0;
+        for ((block, line), height) in self
+            .blocks
+            .iter()
+            .zip(&self.block_lines)
+            .zip(&self.row_heights)
+        {
+            if matches!(block, RenderBlock::Pause(_)) {
+                continue;
+            }
+            top += height + ROW_SPACING;
+            if offset < top {
+                return Some(*line);
+            }
+        }
+        None
+    }
 
-                        ui.add_space(10.0);
+    // The color of the first heading role the text matches that the theme has a color for,
+    // or else the level's color
+    fn heading_color(&self, level: usize, text: &str) -> Color32 {
+        let [r, g, b] = self
+            .heading_role_patterns
+            .iter()
+            .filter(|(pattern, _)| pattern.is_match(text))
+            .find_map(|(_, color)| self.current_theme.role_colors.get(color))
+            .copied()
+            .unwrap_or(self.current_theme.heading_colors[level - 1]);
+        self.shown_color(Color32::from_rgb(r, g, b))
+    }
 
-                        // Speed controls
-                        ui.label("Scroll Speed");
-                        ui.horizontal(|ui| {
-                            if ui
-                                .add_sized(
-                                    [60.0, 60.0],
-                                    egui::Button::new(egui::RichText::new(ICON_REMOVE).size(36.0)),
-                                )
-                                .clicked()
-                            {
-                                self.scroll_speed = (self.scroll_speed - 10.0).max(10.0);
-                            }
-                            ui.add_space(10.0);
-                            ui.label(
-                                egui::RichText::new(format!("{}px/s", self.scroll_speed as i32))
-                                    .size(20.0),
-                            );
-                            ui.add_space(10.0);
-                            if ui
-                                .add_sized(
-                                    [60.0, 60.0],
-                                    egui::Button::new(egui::RichText::new(ICON_ADD).size(36.0)),
-                                )
-                                .clicked()
-                            {
-                                self.scroll_speed = (self.scroll_speed + 10.0).min(500.0);
-                            }
-                        });
+    // A theme color as it's drawn, which in grayscale mode is a gray of the same luminance so
+    // headings still stand apart by brightness
+    fn shown_color(&self, color: Color32) -> Color32 {
+        if self.settings.grayscale {
+            grayscale(color)
+        } else {
+            color
+        }
+    }
 
-                        ui.add_space(20.0);
-                        ui.separator();
-                        ui.add_space(10.0);
+    // Background and text colors to draw with. When the theme changes they blend from the
+    // ones on screen into the new theme's, unless that's turned off; heading colors snap
+    fn theme_colors(&mut self, ctx: &egui::Context) -> [Color32; 2] {
+        let [r, g, b] = self.current_theme.background_color;
+        let background = Color32::from_rgb(r, g, b);
+        let [r, g, b] = self.current_theme.text_color;
+        let target = [
+            self.shown_color(background),
+            self.shown_color(Color32::from_rgb(r, g, b)),
+        ];
 
-                        // Feature toggles
-                        ui.heading("Settings");
-                        ui.add_space(5.0);
+        let blend = |from: [Color32; 2], to: [Color32; 2], t: f32| {
+            [
+                from[0].lerp_to_gamma(to[0], t),
+                from[1].lerp_to_gamma(to[1], t),
+            ]
+        };
+        if self.theme_fade_to != Some(target) {
+            match self.theme_fade_to {
+                Some(shown) if self.settings.animate_theme_changes => {
+                    self.theme_fade_from = blend(self.theme_fade_from, shown, self.theme_fade);
+                    self.theme_fade = 0.0;
+                }
+                _ => self.theme_fade = 1.0,
+            }
+            self.theme_fade_to = Some(target);
+        }
 
-                        ui.checkbox(&mut self.pause_at_headings, "Pause at Headings");
+        if self.theme_fade < 1.0 {
+            let dt = ctx.input(|i| i.stable_dt).min(MAX_FADE_STEP);
+            self.theme_fade = (self.theme_fade + dt / THEME_TRANSITION_TIME).min(1.0);
+            ctx.request_repaint();
+        }
+        blend(self.theme_fade_from, target, self.theme_fade)
+    }
 
-                        if self.pause_at_headings {
-                            ui.horizontal(|ui| {
-                                ui.label("Duration:");
-                                ui.add(
-                                    egui::Slider::new(&mut self.heading_pause_duration, 0.5..=10.0)
-                                        .suffix("s")
-                                        .text("sec"),
-                                );
-                            });
-                        }
+    // Headings beyond the theme's color list are shown as regular text
+    fn is_heading(&self, block: &RenderBlock) -> bool {
+        matches!(block, RenderBlock::Heading { level, .. }
+            if *level <= self.current_theme.heading_colors.len())
+    }
 
-                        ui.checkbox(&mut self.auto_restart, "Auto Restart");
+    // Text of a row as it's shown, with inline HTML and emoji shortcodes converted as set
+    fn display_text(&self, text: &str) -> String {
+        let text = if self.settings.interpret_html {
+            convert_inline_html(text, true)
+        } else {
+            text.to_string()
+        };
+        if self.settings.emoji_shortcodes {
+            expand_shortcodes(&text)
+        } else {
+            text
+        }
+    }
+
+    // What to show before a list item's text, and the text after it: the number of an
+    // ordered item as written, a ballot box for a task, or a bullet that changes with depth
+    fn list_marker<'a>(&self, marker: &str, depth: usize, text: &'a str) -> (String, &'a str) {
+        if marker.ends_with(['.', ')']) {
+            return (marker.to_string(), text);
+        }
+        if self.settings.markdown_extensions.tasklist {
+            if let Some(task) = text.strip_prefix("[ ] ") {
+                return ("☐".to_string(), task);
+            }
+            if let Some(task) = text
+                .strip_prefix("[x] ")
+                .or_else(|| text.strip_prefix("[X] "))
+            {
+                return ("☑".to_string(), task);
+            }
+        }
+        (LIST_BULLETS[depth % LIST_BULLETS.len()].to_string(), text)
+    }
+
+    fn render_block(&self, ui: &mut egui::Ui, block: &RenderBlock, text_color: Color32) {
+        match block {
+            RenderBlock::Heading { level, text } if self.is_heading(block) => {
+                self.render_heading(ui, *level, text);
+            }
+            RenderBlock::Heading { text, .. } | RenderBlock::Paragraph(text) => {
+                // Regular text - use the formatted text renderer
+                let text = self.display_text(text);
+                ui.with_layout(self.settings.text_alignment.layout(), |ui| {
+                    self.render_formatted_text(ui, &text, text_color, self.font_size);
+                });
+            }
+            RenderBlock::Term(text) => {
+                // Emphasized, unless it has emphasis of its own, which doesn't nest
+                let text = self.display_text(text);
+                let text = if text.contains(['*', '_']) {
+                    text
+                } else {
+                    format!("*{}*", text)
+                };
+                self.render_formatted_text(ui, &text, text_color, self.font_size);
+            }
+            RenderBlock::Definition(text) => {
+                let text = self.display_text(text);
+                let available = ui.available_rect_before_wrap();
+                let indented = egui::UiBuilder::new()
+                    .max_rect(available.with_min_x(available.left() + self.font_size * 2.0))
+                    .layout(egui::Layout::top_down(egui::Align::Min));
+                ui.scope_builder(indented, |ui| {
+                    self.render_formatted_text(ui, &text, text_color, self.font_size);
+                });
+            }
+            RenderBlock::ListItem {
+                depth,
+                marker,
+                text,
+            } => {
+                // Each level is indented by the same amount, with the marker hanging in the
+                // indent so wrapped lines line up with the text
+                let (marker, text) = self.list_marker(marker, *depth, text);
+                let text = self.display_text(text);
+                let available = ui.available_rect_before_wrap();
+                let text_left =
+                    available.left() + self.font_size * LIST_INDENT * (*depth + 1) as f32;
+                let item = egui::UiBuilder::new()
+                    .max_rect(available.with_min_x(text_left))
+                    .layout(egui::Layout::top_down(egui::Align::Min));
+                let rect = ui
+                    .scope_builder(item, |ui| {
+                        self.render_formatted_text(ui, &text, text_color, self.font_size);
+                    })
+                    .response
+                    .rect;
+                ui.painter().text(
+                    egui::pos2(text_left - self.font_size * LIST_MARKER_GAP, rect.top()),
+                    egui::Align2::RIGHT_TOP,
+                    marker,
+                    egui::FontId::proportional(self.font_size),
+                    text_color,
+                );
+            }
+            RenderBlock::IndentedCode(text) => {
+                self.render_code_line(ui, text, text_color, self.font_size);
+            }
+            RenderBlock::CodeBlock { language, code } => {
+                self.render_code_block(ui, language, code, text_color, self.font_size);
+            }
+            RenderBlock::Note(text) => {
+                // Presenter notes are for the operator, so they stay quiet
+                ui.label(
+                    egui::RichText::new(text)
+                        .italics()
+                        .size(self.font_size * 0.8)
+                        .color(text_color.gamma_multiply(0.5)),
+                );
+            }
+            RenderBlock::Image { alt, path } => {
+                // Centered and scaled to the width, but no taller than the banner height
+                let max_height = self.settings.banner_max_height;
+                ui.vertical_centered(|ui| match self.banners.get(path) {
+                    Some(Some(texture)) => {
+                        let size = texture.size_vec2();
+                        let scale = (ui.available_width() / size.x).min(max_height / size.y);
+                        ui.add(
+                            egui::Image::new(egui::load::SizedTexture::from_handle(texture))
+                                .fit_to_exact_size(size * scale),
+                        );
+                    }
+                    _ => {
+                        let text = if alt.is_empty() { path } else { alt };
+                        ui.label(
+                            egui::RichText::new(text)
+                                .italics()
+                                .size(self.font_size)
+                                .color(text_color.gamma_multiply(0.5)),
+                        );
+                    }
+                });
+            }
+            RenderBlock::Alert { kind, text } => {
+                // A tinted box in the alert's color, titled with its icon and kind
+                let color = self.shown_color(self.current_theme.alert_color(*kind));
+                let text = self.display_text(text);
+                egui::Frame::NONE
+                    .fill(color.gamma_multiply(ALERT_FILL_ALPHA))
+                    .stroke(egui::Stroke::new(1.0, color.gamma_multiply(0.6)))
+                    .corner_radius(4.0)
+                    .inner_margin(egui::Margin::same((self.font_size * 0.5).round() as i8))
+                    .show(ui, |ui| {
+                        ui.set_width(ui.available_width());
+                        ui.label(
+                            RichText::new(format!("{} {}", kind.icon(), kind.label()))
+                                .size(self.font_size)
+                                .strong()
+                                .color(color),
+                        );
+                        self.render_formatted_text(ui, &text, text_color, self.font_size);
+                    });
+            }
+            RenderBlock::Pause(_) | RenderBlock::Gap => {}
+            RenderBlock::Rule => {
+                ui.separator();
+            }
+        }
+    }
+
+    // Lay out the content for one column of the content panel. Later columns show the same
+    // content further on, so only the first records its layout
+    fn render_column(
+        &mut self,
+        ui: &mut egui::Ui,
+        text_color: Color32,
+        placeholder_color: Color32,
+        viewport_height: f32,
+        record_layout: bool,
+    ) {
+        // In centered mode, half a viewport of blank space above the first
+        // line and below the last lets any line reach the middle, so the
+        // focus line is the vertical center rather than the top edge
+        let center_padding = if self.settings.center_current_line {
+            viewport_height / 2.0
+        } else {
+            0.0
+        };
+
+        if self.show_raw && !self.content.is_empty() {
+            // Plain source view, still driven by scroll_position
+            ui.add_space(center_padding + self.lead_in);
+            let source = self
+                .content
+                .lines()
+                .map(expand_tabs)
+                .collect::<Vec<_>>()
+                .join("\n");
+            ui.label(
+                RichText::new(source)
+                    .monospace()
+                    .size(self.font_size * 0.9)
+                    .color(text_color),
+            );
+            ui.add_space(center_padding);
+
+            // There are no headings or cues to pause at in the source view
+            if record_layout {
+                self.heading_offsets.clear();
+                self.pause_cues.clear();
+                self.paragraph_offsets.clear();
+            }
+        } else if !self.parsed_content.is_empty() {
+            ui.add_space(center_padding + self.lead_in);
+
+            self.render_markdown(ui, text_color, record_layout);
+
+            ui.add_space(center_padding);
+        } else {
+            ui.colored_label(
+                placeholder_color,
+                RichText::new("Open a markdown file to begin.").size(48.0),
+            );
+        }
+    }
+
+    // Thin strip showing the open file's path, its word and line counts and the theme
+    fn render_status_bar(&self, ui: &mut egui::Ui, color: Color32) {
+        ui.horizontal(|ui| {
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.colored_label(color, format!("Theme: {}", self.current_theme.name));
+                ui.separator();
+                ui.colored_label(color, format!("{} lines", self.line_count));
+                ui.separator();
+                ui.colored_label(color, format!("{} words", self.word_count));
+                ui.separator();
+                if !self.content.is_empty() {
+                    ui.colored_label(color, format!("{:.0}%", self.progress_percent()));
+                    ui.separator();
+                }
+                if let Some(warning) = &self.encoding_warning {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("{} Not UTF-8", ICON_WARNING),
+                    )
+                    .on_hover_text(warning);
+                    ui.separator();
+                }
+                if self.settings.lint_markup && !self.lint_lines.is_empty() {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!("{} {} unclosed markup", ICON_WARNING, self.lint_lines.len()),
+                    )
+                    .on_hover_text(
+                        "Lines with an unclosed *, _, ` or ~~ are flagged beside the text",
+                    );
+                    ui.separator();
+                }
+
+                ui.with_layout(egui::Layout::left_to_right(egui::Align::Center), |ui| {
+                    let Some(path) = &self.current_file else {
+                        ui.colored_label(color, "No file open");
+                        return;
+                    };
+
+                    // Elide the directory rather than the file name when space runs out
+                    let file_name = path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    let directory = path
+                        .parent()
+                        .map(|parent| format!("{}{}", parent.display(), std::path::MAIN_SEPARATOR))
+                        .unwrap_or_default();
+                    let name_width = ui
+                        .painter()
+                        .layout_no_wrap(
+                            file_name.clone(),
+                            egui::TextStyle::Body.resolve(ui.style()),
+                            color,
+                        )
+                        .size()
+                        .x;
+                    let directory_width = (ui.available_width() - name_width).max(0.0);
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    ui.allocate_ui(egui::vec2(directory_width, ui.available_height()), |ui| {
+                        ui.add(egui::Label::new(RichText::new(directory).color(color)).truncate());
+                    });
+                    ui.colored_label(color, file_name);
+                });
+            });
+        });
+    }
+
+    // Render a line of an indented code block verbatim in monospace
+    fn render_code_line(&self, ui: &mut egui::Ui, text: &str, color: Color32, base_size: f32) {
+        use egui::{text::LayoutJob, TextFormat};
+
+        let mut job = LayoutJob::default();
+        job.append(
+            text,
+            0.0,
+            TextFormat {
+                font_id: self.code_font(base_size),
+                color,
+                background: self.shown_color(self.current_theme.inline_code_background()),
+                ..Default::default()
+            },
+        );
+        ui.label(job);
+    }
+
+    // Write edits to the current theme back to the theme list and themes.toml
+    fn update_current_theme(&mut self) {
+        if let Some(theme) = self
+            .available_themes
+            .iter_mut()
+            .find(|t| t.name == self.current_theme.name)
+        {
+            *theme = self.current_theme.clone();
+        }
+        if let Err(e) = save_themes(&self.available_themes, &self.default_theme) {
+            eprintln!("Failed to save themes: {}", e);
+        }
+    }
+
+    fn import_theme(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("Theme", &["toml", "json"])
+            .pick_file()
+        {
+            match load_theme_file(&path) {
+                Ok(mut theme) => {
+                    theme.name = unique_theme_name(&self.available_themes, &theme.name);
+                    self.available_themes.push(theme.clone());
+                    self.current_theme = theme;
+                    self.apply_theme_font_size();
+
+                    // Persist the new theme along with the selection
+                    if let Err(e) = save_themes(&self.available_themes, &self.current_theme.name) {
+                        eprintln!("Failed to save themes: {}", e);
+                    }
+                    self.theme_status = Some(Ok(format!(
+                        "Imported theme \"{}\"",
+                        self.current_theme.name
+                    )));
+                }
+                Err(e) => {
+                    self.theme_status = Some(Err(format!("Failed to import theme: {}", e)));
+                }
+            }
+        }
+    }
+
+    fn export_theme(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("Theme", &["toml", "json"])
+            .set_file_name(format!("{}.toml", self.current_theme.name))
+            .save_file()
+        {
+            self.theme_status = Some(match save_theme_file(&path, &self.current_theme) {
+                Ok(()) => Ok(format!("Exported theme \"{}\"", self.current_theme.name)),
+                Err(e) => Err(format!("Failed to export theme: {}", e)),
+            });
+        }
+    }
+
+    // The script as plain text for tools that don't read markdown, built from the parsed
+    // blocks: emphasis, code markers and link targets dropped, headings uppercased or kept
+    // with their "#"s, and list items with their bullets or numbers. Pause cues and notes
+    // are left out
+    fn plain_text(&self) -> String {
+        let strikethrough = self.settings.markdown_extensions.strikethrough;
+        let strip = |text: &str| {
+            let text = self.display_text(text);
+            strip_inline_markup(&text, &self.link_definitions, strikethrough)
+                .trim_end()
+                .to_string()
+        };
+        let mut lines = Vec::new();
+        for block in &self.blocks {
+            lines.push(match block {
+                RenderBlock::Heading { level, text } if self.settings.export_heading_markup => {
+                    format!("{} {}", "#".repeat(*level), strip(text))
+                }
+                RenderBlock::Heading { text, .. } => strip(text).to_uppercase(),
+                RenderBlock::Paragraph(text) | RenderBlock::Term(text) => strip(text),
+                RenderBlock::Definition(text) => format!("{}{}", CODE_INDENT, strip(text)),
+                RenderBlock::ListItem {
+                    depth,
+                    marker,
+                    text,
+                } => {
+                    let (marker, text) = self.list_marker(marker, *depth, text);
+                    format!("{}{} {}", "  ".repeat(*depth), marker, strip(text))
+                }
+                RenderBlock::IndentedCode(code) => format!("{}{}", CODE_INDENT, code),
+                RenderBlock::CodeBlock { code, .. } => code.trim_end_matches('\n').to_string(),
+                RenderBlock::Alert { kind, text } => {
+                    format!("{}: {}", kind.label().to_uppercase(), strip(text))
+                }
+                RenderBlock::Rule => "---".to_string(),
+                RenderBlock::Gap => String::new(),
+                RenderBlock::Pause(_) | RenderBlock::Note(_) | RenderBlock::Image { .. } => {
+                    continue
+                }
+            });
+        }
+        lines.join("\n") + "\n"
+    }
+
+    fn export_plain_text(&mut self) {
+        let name = self
+            .current_file
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .map_or("script".to_string(), |stem| {
+                stem.to_string_lossy().to_string()
+            });
+        if let Some(path) = FileDialog::new()
+            .add_filter("Plain text", &["txt"])
+            .set_file_name(format!("{}.txt", name))
+            .save_file()
+        {
+            self.export_status = Some(match fs::write(&path, self.plain_text()) {
+                Ok(()) => Ok(format!("Exported {}", path.display())),
+                Err(e) => Err(format!("Failed to export plain text: {}", e)),
+            });
+        }
+    }
+
+    fn export_config_bundle(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("Config bundle", &["toml"])
+            .set_file_name("markprompter-config.toml")
+            .save_file()
+        {
+            let bundle = ConfigBundle {
+                settings: self.settings.clone(),
+                themes: self.available_themes.clone(),
+            };
+            self.theme_status = Some(match save_config_bundle(&path, &bundle) {
+                Ok(()) => Ok(format!(
+                    "Exported settings and {} themes",
+                    bundle.themes.len()
+                )),
+                Err(e) => Err(format!("Failed to export config bundle: {}", e)),
+            });
+        }
+    }
+
+    // Merge a config bundle into the current configuration. Themes with new names are added
+    // straight away; those whose names are taken wait for a choice in the conflict window
+    fn import_config_bundle(&mut self) {
+        let Some(path) = FileDialog::new()
+            .add_filter("Config bundle", &["toml"])
+            .pick_file()
+        else {
+            return;
+        };
+        let bundle = match load_config_bundle(&path) {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                self.theme_status = Some(Err(format!("Failed to import config bundle: {}", e)));
+                return;
+            }
+        };
+
+        // Speeds here are converted to the bundle's unit first, so the current speed and the
+        // per-file ones kept below still mean the same thing afterwards
+        let mut settings = bundle.settings;
+        settings.validate();
+        self.set_speed_unit(settings.speed_unit);
+        self.preserve_scroll_fraction();
+
+        // Per-file entries already on this machine win over the bundle's
+        for (file, entry) in std::mem::take(&mut self.settings.files) {
+            settings.files.insert(file, entry);
+        }
+
+        // Paths and window geometry belong to the machine the bundle came from, so only what
+        // also exists here is taken; the window always stays where it is on this one
+        settings.window = self.settings.window.take();
+        if !settings
+            .code_font
+            .as_ref()
+            .is_some_and(|path| path.is_file())
+        {
+            settings.code_font = self.settings.code_font.take();
+        }
+        if !settings
+            .last_directory
+            .as_ref()
+            .is_some_and(|path| path.is_dir())
+        {
+            settings.last_directory = self.settings.last_directory.take();
+        }
+        let mut playlist = std::mem::take(&mut self.settings.playlist);
+        for path in std::mem::take(&mut settings.playlist) {
+            if path.is_file() && !playlist.contains(&path) {
+                playlist.push(path);
+            }
+        }
+        settings.playlist = playlist;
+
+        self.settings = settings;
+        self.scroll_speed = self.settings.clamp_speed(self.scroll_speed);
+        self.compile_heading_roles();
+        self.apply_fonts();
+        self.ui_scale_applied = false;
+        self.apply_theme_font_size();
+        self.save_settings();
+
+        let mut added = 0;
+        for theme in bundle.themes {
+            if self.available_themes.iter().any(|t| t.name == theme.name) {
+                self.theme_conflicts.push(theme);
+            } else {
+                self.available_themes.push(theme);
+                added += 1;
+            }
+        }
+        if let Err(e) = save_themes(&self.available_themes, &self.default_theme) {
+            eprintln!("Failed to save themes: {}", e);
+        }
+        self.theme_status = Some(Ok(format!("Imported settings and {} new themes", added)));
+    }
+
+    // Ask what to do with each imported theme whose name is already taken
+    fn show_theme_conflicts(&mut self, ctx: &egui::Context) {
+        let Some(theme) = self.theme_conflicts.first() else {
+            return;
+        };
+        let mut choice = None;
+        egui::Window::new("Theme Already Exists")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The imported config bundle has a theme named \"{}\", which already exists.",
+                    theme.name
+                ));
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Replace").clicked() {
+                        choice = Some(ThemeConflictChoice::Replace);
+                    }
+                    if ui.button("Keep Both").clicked() {
+                        choice = Some(ThemeConflictChoice::KeepBoth);
+                    }
+                    if ui.button("Skip").clicked() {
+                        choice = Some(ThemeConflictChoice::Skip);
+                    }
+                });
+            });
+
+        let Some(choice) = choice else {
+            return;
+        };
+        let mut theme = self.theme_conflicts.remove(0);
+        match choice {
+            ThemeConflictChoice::Replace => {
+                if theme.name == self.current_theme.name {
+                    self.current_theme = theme.clone();
+                }
+                if let Some(existing) = self
+                    .available_themes
+                    .iter_mut()
+                    .find(|t| t.name == theme.name)
+                {
+                    *existing = theme;
+                }
+            }
+            ThemeConflictChoice::KeepBoth => {
+                theme.name = unique_theme_name(&self.available_themes, &theme.name);
+                self.available_themes.push(theme);
+            }
+            ThemeConflictChoice::Skip => return,
+        }
+        if let Err(e) = save_themes(&self.available_themes, &self.default_theme) {
+            eprintln!("Failed to save themes: {}", e);
+        }
+    }
+
+    // Render a fenced code block, syntax highlighted when its language is known
+    fn render_code_block(
+        &self,
+        ui: &mut egui::Ui,
+        language: &str,
+        code: &str,
+        color: Color32,
+        base_size: f32,
+    ) {
+        use egui::{text::LayoutJob, TextFormat};
+
+        let dark_background = self.current_theme.is_dark();
+        let segments = self
+            .highlighter
+            .highlight(language, code, dark_background)
+            .unwrap_or_else(|| vec![(color, code.to_string())]);
+
+        // Code blocks share the theme's inline code background
+        let background = self.shown_color(self.current_theme.inline_code_background());
+        let mut job = LayoutJob::default();
+        for (segment_color, text) in segments {
+            job.append(
+                &text,
+                0.0,
+                TextFormat {
+                    font_id: self.code_font(base_size),
+                    color: self.shown_color(segment_color),
+                    background,
+                    ..Default::default()
+                },
+            );
+        }
+        ui.label(job);
+    }
+
+    fn open_file(&mut self) {
+        let mut dialog = FileDialog::new().add_filter("Markdown", &MARKDOWN_EXTENSIONS);
+
+        // Start where the last file was opened, unless that folder has since gone away
+        if let Some(directory) = self.settings.last_directory.as_ref().filter(|d| d.is_dir()) {
+            dialog = dialog.set_directory(directory);
+        }
+        if let Some(path) = dialog.pick_file() {
+            self.load_file(path);
+        }
+    }
+
+    // Load the images shown as banners, afresh so edits to them show up on reload
+    fn load_banners(&mut self) {
+        self.banners.clear();
+        for block in &self.blocks {
+            if let RenderBlock::Image { path, .. } = block {
+                if self.banners.contains_key(path) {
+                    continue;
+                }
+                let texture = match self.load_banner(path) {
+                    Ok(texture) => Some(texture),
+                    Err(e) => {
+                        eprintln!("Failed to load image {}: {}", path, e);
+                        None
+                    }
+                };
+                self.banners.insert(path.clone(), texture);
+            }
+        }
+    }
+
+    // Image paths are relative to the script they're in
+    fn load_banner(&self, path: &str) -> Result<egui::TextureHandle, Box<dyn std::error::Error>> {
+        let full_path = match self.current_file.as_ref().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+        let image = image::open(full_path)?.to_rgba8();
+        let size = [image.width() as usize, image.height() as usize];
+        let pixels = egui::ColorImage::from_rgba_unmultiplied(size, image.as_raw());
+        Ok(self
+            .egui_ctx
+            .load_texture(path, pixels, egui::TextureOptions::LINEAR))
+    }
+
+    // Open a script, returning whether it could be read
+    fn load_file(&mut self, path: PathBuf) -> bool {
+        self.store_resume_position();
+        self.calibration = None;
+        self.reset_cue_log();
+        match read_script(&path) {
+            Ok((content, encoding_warning)) => {
+                self.content = content;
+                self.encoding_warning = encoding_warning;
+                self.start_fade_in();
+                // Set first, so banner images are found next to the new file
+                self.current_file = Some(path.clone());
+                self.file_missing = false;
+                self.parse_markdown();
+                self.undo_reloads.clear();
+                self.redo_reloads.clear();
+                self.seek_to(0.0);
+
+                let directory = fs::canonicalize(&path)
+                    .ok()
+                    .and_then(|full_path| full_path.parent().map(PathBuf::from));
+                if directory.is_some() && directory != self.settings.last_directory {
+                    self.settings.last_directory = directory;
+                    self.save_settings();
+                }
+                self.restore_markers();
+                self.collapsed_sections = self
+                    .current_file_key()
+                    .and_then(|key| self.settings.files.get(&key))
+                    .map(|file_settings| file_settings.collapsed_sections.clone())
+                    .unwrap_or_default();
+                self.apply_file_settings();
+                self.restore_resume_position();
+
+                self.file_watcher = Some(watch_file(path, self.egui_ctx.clone()));
+                self.kiosk_start = self.settings.kiosk_mode.then(Instant::now);
+                true
+            }
+            Err(e) => {
+                eprintln!("Error loading file: {}", e);
+                false
+            }
+        }
+    }
+
+    fn parse_markdown(&mut self) {
+        let extensions = self.settings.markdown_extensions;
+        let mut options = ComrakOptions::default();
+        options.extension.strikethrough = extensions.strikethrough;
+        options.extension.table = extensions.table;
+        options.extension.tasklist = extensions.tasklist;
+        options.extension.footnotes = extensions.footnotes;
+
+        self.parsed_content = markdown_to_html(&self.content, &options);
+        (self.block_lines, self.blocks) = parse_blocks(
+            &self.content,
+            self.settings.newlines_as_breaks,
+            self.settings.show_notes,
+        )
+        .into_iter()
+        .unzip();
+        self.link_definitions = link_definitions(&self.content);
+        self.load_banners();
+        self.annotations = pause_cue_annotations(&self.blocks, &self.block_lines);
+        let strikethrough = extensions.strikethrough;
+        self.lint_lines = self
+            .blocks
+            .iter()
+            .zip(&self.block_lines)
+            .filter(|(block, _)| match block {
+                RenderBlock::Heading { text, .. }
+                | RenderBlock::Paragraph(text)
+                | RenderBlock::Term(text)
+                | RenderBlock::Definition(text)
+                | RenderBlock::ListItem { text, .. }
+                | RenderBlock::Alert { text, .. } => has_unclosed_markup(text, strikethrough),
+                _ => false,
+            })
+            .map(|(_, &line)| line)
+            .collect();
+        self.word_count = count_words(&extract_notes(&self.content).0);
+        self.section_words = vec![0];
+        for block in &self.blocks {
+            if self.is_heading(block) {
+                self.section_words.push(0);
+            }
+            let Some(text) = block_text(block) else {
+                continue;
+            };
+            if let Some(words) = self.section_words.last_mut() {
+                *words += count_words(text);
+            }
+        }
+        if self.settings.number_headings {
+            let levels = self.current_theme.heading_colors.len();
+            number_headings(&mut self.blocks, levels);
+        }
+        self.line_count = self.content.lines().count();
+
+        // Heading and cue offsets and row heights are re-captured from the layout on the next
+        // frame
+        self.heading_offsets.clear();
+        self.pause_cues.clear();
+        self.paragraph_offsets.clear();
+        self.row_heights.clear();
+        self.highlighter.clear_cache();
+    }
+
+    // Where the open file is in the playlist
+    fn playlist_position(&self) -> Option<usize> {
+        let current = self.current_file.as_ref()?;
+        self.settings
+            .playlist
+            .iter()
+            .position(|path| path == current)
+    }
+
+    // Open the next or previous script in the playlist, from the start. A file that isn't in
+    // the playlist is followed by its first entry. Returns whether one was opened, so a
+    // missing file stops auto-advance instead of being retried
+    fn step_playlist(&mut self, forward: bool) -> bool {
+        let target = match (self.playlist_position(), forward) {
+            (Some(index), true) => index + 1,
+            (Some(index), false) => match index.checked_sub(1) {
+                Some(previous) => previous,
+                None => return false,
+            },
+            (None, true) => 0,
+            (None, false) => return false,
+        };
+        match self.settings.playlist.get(target).cloned() {
+            Some(path) => {
+                let loaded = self.load_file(path);
+                // Playlist scripts start from the top, where resuming would have picked up
+                self.scroll_fraction = None;
+                self.resumed = false;
+                loaded
+            }
+            None => false,
+        }
+    }
+
+    fn add_to_playlist(&mut self) {
+        let mut dialog = FileDialog::new().add_filter("Markdown", &MARKDOWN_EXTENSIONS);
+        if let Some(directory) = self.settings.last_directory.as_ref().filter(|d| d.is_dir()) {
+            dialog = dialog.set_directory(directory);
+        }
+        if let Some(paths) = dialog.pick_files() {
+            self.settings.playlist.extend(paths);
+            self.save_settings();
+        }
+    }
+
+    // Queued scripts for a show in several parts, which can be reordered and stepped through
+    fn render_playlist(&mut self, ui: &mut egui::Ui) {
+        let current = self.playlist_position();
+        ui.collapsing(
+            format!("Playlist ({})", self.settings.playlist.len()),
+            |ui| {
+                let mut edit = None;
+                let count = self.settings.playlist.len();
+                for (index, path) in self.settings.playlist.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        let name = path.file_name().unwrap_or_default().to_string_lossy();
+                        if ui
+                            .selectable_label(
+                                current == Some(index),
+                                format!("{}. {}", index + 1, name),
+                            )
+                            .on_hover_text(path.display().to_string())
+                            .clicked()
+                        {
+                            edit = Some(PlaylistEdit::Open(index));
+                        }
+                        if ui
+                            .add_enabled(index > 0, egui::Button::new(ICON_ARROW_UPWARD))
+                            .clicked()
+                        {
+                            edit = Some(PlaylistEdit::Swap(index - 1, index));
+                        }
+                        if ui
+                            .add_enabled(index + 1 < count, egui::Button::new(ICON_ARROW_DOWNWARD))
+                            .clicked()
+                        {
+                            edit = Some(PlaylistEdit::Swap(index, index + 1));
+                        }
+                        if ui.button(ICON_CLOSE).on_hover_text("Remove").clicked() {
+                            edit = Some(PlaylistEdit::Remove(index));
+                        }
+                    });
+                }
+                match edit {
+                    Some(PlaylistEdit::Open(index)) => {
+                        let path = self.settings.playlist[index].clone();
+                        self.load_file(path);
+                    }
+                    Some(PlaylistEdit::Swap(a, b)) => {
+                        self.settings.playlist.swap(a, b);
+                        self.save_settings();
+                    }
+                    Some(PlaylistEdit::Remove(index)) => {
+                        self.settings.playlist.remove(index);
+                        self.save_settings();
+                    }
+                    None => {}
+                }
+
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            current.is_some_and(|index| index > 0),
+                            egui::Button::new(format!("{} Previous", ICON_NAVIGATE_BEFORE)),
+                        )
+                        .clicked()
+                    {
+                        self.step_playlist(false);
+                    }
+                    let has_next = current.map_or(count > 0, |index| index + 1 < count);
+                    if ui
+                        .add_enabled(
+                            has_next,
+                            egui::Button::new(format!("Next {}", ICON_NAVIGATE_NEXT)),
+                        )
+                        .clicked()
+                    {
+                        self.step_playlist(true);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .button(format!("{} Add Files…", ICON_PLAYLIST_ADD))
+                        .clicked()
+                    {
+                        self.add_to_playlist();
+                    }
+                    if let Some(path) = self.current_file.clone().filter(|_| current.is_none()) {
+                        if ui.button("Add Current").clicked() {
+                            self.settings.playlist.push(path);
+                            self.save_settings();
+                        }
+                    }
+                });
+                if ui
+                    .checkbox(&mut self.settings.playlist_auto_advance, "Advance at End")
+                    .on_hover_text(
+                        "Open the next script when one finishes, when End Behavior is Stop",
+                    )
+                    .changed()
+                {
+                    self.save_settings();
+                }
+            },
+        );
+    }
+
+    fn check_file_updates(&mut self) {
+        let event = self
+            .file_watcher
+            .as_ref()
+            .and_then(|watcher| watcher.events.try_recv().ok());
+        match event {
+            Some(WatchEvent::Modified) => self.reload_file(),
+            Some(WatchEvent::Deleted) => {
+                eprintln!("Watched file no longer exists");
+                self.file_missing = true;
+            }
+            None => {}
+        }
+    }
+
+    // Pick up edits to themes.toml made outside the app, keeping the selected theme by name
+    fn check_themes_updates(&mut self) {
+        let event = self
+            .themes_watcher
+            .as_ref()
+            .and_then(|watcher| watcher.events.try_recv().ok());
+        match event {
+            Some(WatchEvent::Modified) => self.reload_themes(),
+            Some(WatchEvent::Deleted) => {
+                self.themes_error = Some(format!(
+                    "{} no longer exists, keeping the loaded themes",
+                    THEMES_PATH
+                ));
+            }
+            None => {}
+        }
+    }
+
+    fn reload_themes(&mut self) {
+        let loaded = load_themes_and_preference().and_then(|(themes, _)| {
+            themes.iter().try_for_each(validate_theme)?;
+            if themes.is_empty() {
+                return Err("no themes defined".into());
+            }
+            Ok(themes)
+        });
+        match loaded {
+            // Nothing changed, as when the file was saved from here
+            Ok(themes) if themes == self.available_themes => {
+                self.themes_error = None;
+            }
+            Ok(themes) => {
+                eprintln!("Reloaded {} themes from {}", themes.len(), THEMES_PATH);
+                self.themes_error = None;
+                // Unsaved edits from the color pickers are kept over the copy on disk
+                if let Some(theme) = themes
+                    .iter()
+                    .find(|t| t.name == self.current_theme.name)
+                    .filter(|_| !self.theme_edited)
+                {
+                    self.current_theme = theme.clone();
+                }
+                self.available_themes = themes;
+            }
+            Err(e) => {
+                eprintln!("Error reloading themes: {}", e);
+                self.themes_error = Some(format!("{}: {}", THEMES_PATH, e));
+            }
+        }
+    }
+
+    // Banner above the content once the open file has disappeared, offering to keep
+    // showing what was last loaded or to close it
+    fn render_missing_file_banner(&mut self, ui: &mut egui::Ui) {
+        let name = self
+            .current_file
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        ui.horizontal(|ui| {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("{} {} no longer exists", ICON_WARNING, name),
+            );
+            if ui
+                .button("Keep Content")
+                .on_hover_text("Keep showing the last loaded version")
+                .clicked()
+            {
+                self.file_missing = false;
+            }
+            if ui.button("Close").clicked() {
+                self.close_file();
+            }
+        });
+    }
+
+    fn close_file(&mut self) {
+        self.store_resume_position();
+        self.resumed = false;
+        self.calibration = None;
+        self.reset_cue_log();
+        self.content.clear();
+        self.encoding_warning = None;
+        self.current_file = None;
+        self.file_missing = false;
+        self.file_watcher = None;
+        self.parse_markdown();
+        self.seek_to(0.0);
+    }
+
+    // Re-read the current file from disk, keeping the version shown until now for Undo Reload
+    fn reload_file(&mut self) {
+        if let Some(path) = &self.current_file {
+            if let Ok((content, encoding_warning)) = read_script(path) {
+                self.encoding_warning = encoding_warning;
+                let previous = std::mem::replace(&mut self.content, content);
+                self.undo_reloads.push_back(previous);
+                if self.undo_reloads.len() > RELOAD_HISTORY {
+                    self.undo_reloads.pop_front();
+                }
+                self.redo_reloads.clear();
+                self.parse_markdown();
+                self.start_fade_in();
+            }
+        }
+    }
+
+    fn start_fade_in(&mut self) {
+        if self.settings.fade_in {
+            self.content_fade = 0.0;
+        }
+    }
+
+    // Go back to the version of the file shown before the last reload. Only the displayed
+    // content changes; the file on disk is left alone
+    fn undo_reload(&mut self) {
+        if let Some(previous) = self.undo_reloads.pop_back() {
+            let current = std::mem::replace(&mut self.content, previous);
+            self.redo_reloads.push(current);
+            self.parse_markdown();
+        }
+    }
+
+    fn redo_reload(&mut self) {
+        if let Some(next) = self.redo_reloads.pop() {
+            let current = std::mem::replace(&mut self.content, next);
+            self.undo_reloads.push_back(current);
+            self.parse_markdown();
+        }
+    }
+
+    // Remember how far through the content we are, so the same place can be restored once
+    // a font or width change has been laid out
+    fn preserve_scroll_fraction(&mut self) {
+        if self.content_height > 0.0 && self.scroll_fraction.is_none() {
+            self.scroll_fraction = Some(self.scroll_position / self.content_height);
+        }
+    }
+
+    // A font size picked with the controls, which the theme's font size then leaves alone for
+    // the rest of the session
+    fn choose_font_size(&mut self, font_size: f32) {
+        self.font_size_chosen = true;
+        self.set_font_size(font_size);
+    }
+
+    // Go back to the theme's font size, or the default one, and let themes set it again
+    fn reset_font_size(&mut self) {
+        self.font_size_chosen = false;
+        self.set_font_size(
+            self.current_theme
+                .default_font_size
+                .unwrap_or(self.settings.default_font_size),
+        );
+    }
+
+    // A font size +/- button. It steps once when pressed, or by FONT_SIZE_JUMP with Shift
+    // held, and keeps stepping while held down
+    fn font_step_button(&mut self, ui: &mut egui::Ui, icon: &str, direction: f32) {
+        let response = ui
+            .add_sized(
+                [50.0, 50.0],
+                egui::Button::new(egui::RichText::new(icon).size(32.0)),
+            )
+            .on_hover_text("Hold to repeat, Shift for a bigger step");
+        if !response.is_pointer_button_down_on() {
+            return;
+        }
+
+        let now = Instant::now();
+        let step = match self.font_step_repeat {
+            None => Some(HOLD_REPEAT_DELAY),
+            Some(at) if now >= at => Some(HOLD_REPEAT_INTERVAL),
+            Some(_) => None,
+        };
+        if let Some(next) = step {
+            self.font_step_repeat = Some(now + Duration::from_secs_f32(next));
+            let step = if ui.input(|i| i.modifiers.shift) {
+                FONT_SIZE_JUMP
+            } else {
+                self.settings.font_size_step
+            };
+            self.choose_font_size(self.font_size + direction * step);
+        }
+        ui.ctx().request_repaint();
+    }
+
+    // Switch to the current theme's font size, if it has one and no size has been chosen
+    fn apply_theme_font_size(&mut self) {
+        if let Some(font_size) = self.current_theme.default_font_size {
+            if !self.font_size_chosen {
+                self.set_font_size(font_size);
+            }
+        }
+    }
+
+    fn set_font_size(&mut self, font_size: f32) {
+        let font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+        if font_size == self.font_size {
+            return;
+        }
+        self.preserve_scroll_fraction();
+
+        // Scale the heights of rows that are off screen, as they won't be re-measured yet
+        let scale = font_size / self.font_size;
+        for height in &mut self.row_heights {
+            *height *= scale;
+        }
+        self.font_size = font_size;
+    }
+
+    // Jump to a position, re-arming pauses for headings and cues below it
+    fn seek_to(&mut self, position: f32) {
+        self.scroll_position = position.max(0.0);
+        self.scroll_requested = true;
+        self.current_pause = None;
+        self.lead_in = 0.0;
+        self.rearm_pauses();
+    }
+
+    // Loop back to the start, beginning the configured number of lines before it so the
+    // restart scrolls in rather than snapping. Before a practice range that replays the lines
+    // leading up to it; before the top of the script it's blank space the first line rises
+    // through
+    fn restart_loop(&mut self, start_position: f32) {
+        let lead_in = self.settings.loop_lead_in * self.font_size * LINE_HEIGHT_FACTOR;
+        let position = start_position - lead_in;
+        self.seek_to(position);
+        self.reset_cue_log();
+        self.lead_in = (-position).max(0.0);
+    }
+
+    // Count headings and pause cues above the scroll position as already passed, and the
+    // ones below it as still to come, whichever way the position moved
+    fn rearm_pauses(&mut self) {
+        self.last_checked_heading_idx =
+            next_offset_idx(&self.heading_offsets, self.scroll_position);
+        self.last_checked_cue_idx = self
+            .pause_cues
+            .partition_point(|&(offset, _)| offset < self.scroll_position);
+        self.last_checked_paragraph_idx =
+            next_offset_idx(&self.paragraph_offsets, self.scroll_position);
+        self.cue_log_heading_idx = self.last_checked_heading_idx;
+    }
+
+    // Go back to the start, with a fresh cue log
+    fn restart(&mut self) {
+        self.seek_to(self.playback_bounds().0);
+        self.reset_cue_log();
+    }
+
+    fn reset_cue_log(&mut self) {
+        self.cue_log.clear();
+        self.cue_log_elapsed = 0.0;
+    }
+
+    // Log the headings the position has reached since the last call
+    fn log_passed_headings(&mut self) {
+        while let Some(&offset) = self.heading_offsets.get(self.cue_log_heading_idx) {
+            if self.scroll_position < offset {
+                break;
+            }
+            let heading = self
+                .blocks
+                .iter()
+                .zip(&self.block_lines)
+                .filter(|(block, _)| self.is_heading(block))
+                .nth(self.cue_log_heading_idx);
+            if let Some((RenderBlock::Heading { text, .. }, &line)) = heading {
+                self.cue_log.push(CueLogEntry {
+                    heading: strip_inline_markup(
+                        text,
+                        &self.link_definitions,
+                        self.settings.markdown_extensions.strikethrough,
+                    ),
+                    line,
+                    elapsed: self.cue_log_elapsed,
+                    wall_clock: chrono::Local::now(),
+                });
+            }
+            self.cue_log_heading_idx += 1;
+        }
+    }
+
+    // The cue log as CSV: one row per heading, with its time as set
+    fn cue_log_csv(&self) -> String {
+        let mut csv = String::from("heading,line,time\n");
+        for entry in &self.cue_log {
+            let time = match self.settings.cue_log_time {
+                CueTime::Elapsed => {
+                    let millis = (entry.elapsed * 1000.0).round() as u64;
+                    format!(
+                        "{:02}:{:02}:{:02}.{:03}",
+                        millis / 3_600_000,
+                        millis / 60_000 % 60,
+                        millis / 1000 % 60,
+                        millis % 1000
+                    )
+                }
+                CueTime::WallClock => entry.wall_clock.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            };
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                csv_field(&entry.heading),
+                entry.line,
+                time
+            ));
+        }
+        csv
+    }
+
+    fn export_cue_log(&mut self) {
+        if let Some(path) = FileDialog::new()
+            .add_filter("CSV", &["csv"])
+            .set_file_name("cues.csv")
+            .save_file()
+        {
+            self.export_status = Some(match fs::write(&path, self.cue_log_csv()) {
+                Ok(()) => Ok(format!(
+                    "Exported {} cues to {}",
+                    self.cue_log.len(),
+                    path.display()
+                )),
+                Err(e) => Err(format!("Failed to export cue log: {}", e)),
+            });
+        }
+    }
+
+    // Take the position the ScrollArea ended up at. Wheel, drag and scrollbar input moves it
+    // away from where playback left it, possibly backwards, so pauses are re-armed from there
+    fn adopt_scroll_offset(&mut self, offset: f32) {
+        if (offset - self.scroll_position).abs() > SCROLL_ADOPT_TOLERANCE {
+            self.scroll_position = offset;
+            self.rearm_pauses();
+        } else {
+            self.scroll_position = offset;
+        }
+    }
+
+    // Move up or down by some distance, staying within the content
+    fn scroll_by(&mut self, distance: f32) {
+        let max_position = self.max_scroll_position();
+        self.seek_to((self.scroll_position + distance).min(max_position));
+    }
+
+    // How far through the script we are, where 100% has the end of the content in view
+    fn progress_percent(&self) -> f32 {
+        let max_position = self.max_scroll_position();
+        if max_position > 0.0 {
+            (self.scroll_position / max_position * 100.0).min(100.0)
+        } else {
+            0.0
+        }
+    }
+
+    fn jump_to_percent(&mut self, percent: f32) {
+        self.seek_to(percent.clamp(0.0, 100.0) / 100.0 * self.max_scroll_position());
+    }
+
+    fn column_count(&self) -> usize {
+        if self.settings.two_columns {
+            2
+        } else {
+            1
+        }
+    }
+
+    // Scroll position at which the end of the content comes into view in the last column
+    fn max_scroll_position(&self) -> f32 {
+        (self.content_height - self.viewport_height * self.column_count() as f32).max(0.0)
+    }
+
+    // Index of the heading whose section is at the top of the screen
+    fn current_section(&self) -> Option<usize> {
+        self.heading_offsets
+            .partition_point(|&offset| offset <= self.scroll_position + 1.0)
+            .checked_sub(1)
+    }
+
+    // With state events on, print a line of JSON whenever playback starts or stops, or a new
+    // section scrolls into place, for tools like OBS to follow. Nothing is printed otherwise
+    fn report_state_changes(&mut self) {
+        if !self.settings.state_events {
+            return;
+        }
+        if self.is_playing != self.reported_playing {
+            self.reported_playing = self.is_playing;
+            let event = if self.is_playing { "playing" } else { "paused" };
+            print_state_event(serde_json::json!({ "event": event }));
+        }
+        let section = self.current_section();
+        if section != self.reported_section {
+            self.reported_section = section;
+            if let Some(index) = section {
+                let heading = self
+                    .blocks
+                    .iter()
+                    .filter(|block| self.is_heading(block))
+                    .nth(index);
+                if let Some(RenderBlock::Heading { level, text }) = heading {
+                    // As it's shown, like the plain text export has it
+                    let text = strip_inline_markup(
+                        &self.display_text(text),
+                        &self.link_definitions,
+                        self.settings.markdown_extensions.strikethrough,
+                    );
+                    print_state_event(serde_json::json!({
+                        "event": "section",
+                        "heading": text,
+                        "level": level,
+                    }));
+                }
+            }
+        }
+    }
+
+    // Playback stopping at the end is reported as "ended" rather than "paused"
+    fn report_end(&mut self) {
+        if self.settings.state_events {
+            self.reported_playing = false;
+            print_state_event(serde_json::json!({ "event": "ended" }));
+        }
+    }
+
+    // Jump to the previous or next heading
+    fn jump_to_heading(&mut self, forward: bool) {
+        let idx = if forward {
+            self.heading_offsets
+                .iter()
+                .position(|&offset| offset > self.scroll_position + 1.0)
+        } else {
+            self.heading_offsets
+                .iter()
+                .rposition(|&offset| offset < self.scroll_position - 1.0)
+        };
+        if let Some(idx) = idx {
+            self.jump_to_section(idx);
+        }
+    }
+
+    // Jump to a heading. It counts as already passed, so playback doesn't pause on it
+    // straight away
+    fn jump_to_section(&mut self, idx: usize) {
+        let Some(&offset) = self.heading_offsets.get(idx) else {
+            return;
+        };
+        self.seek_to(offset);
+        self.last_checked_heading_idx = idx + 1;
+        self.last_checked_paragraph_idx = self
+            .paragraph_offsets
+            .partition_point(|&offset| offset <= self.scroll_position);
+    }
+
+    // Click on each beat while playing, counting real time so pauses keep the beat going
+    fn tick_metronome(&mut self, dt: f32) {
+        if !self.settings.metronome || !self.is_playing {
+            self.metronome_beat = 0.0;
+            return;
+        }
+        self.metronome_beat += dt * self.metronome_bpm() / 60.0;
+        if self.metronome_beat >= 1.0 {
+            self.metronome_beat = self.metronome_beat.fract();
+            self.sounds.click();
+        }
+    }
+
+    // The set tempo, or the words per minute the whole script reads at with the current speed
+    fn metronome_bpm(&self) -> f32 {
+        let pace = self
+            .reading_seconds(0.0, self.content_height, false)
+            .filter(|&seconds| seconds > 0.0 && self.word_count > 0)
+            .map(|seconds| self.word_count as f32 * 60.0 / seconds);
+        match pace {
+            Some(words_per_minute) if self.settings.metronome_follows_pace => {
+                words_per_minute.clamp(MIN_METRONOME_BPM, MAX_METRONOME_BPM)
+            }
+            _ => self.settings.metronome_bpm,
+        }
+    }
+
+    // Start measuring the reading pace from the current position, scrolling as it's read aloud
+    fn start_calibration(&mut self) {
+        if self.current_file.is_none() {
+            return;
+        }
+        self.calibration = Some(Calibration::Reading {
+            started: Instant::now(),
+            start_offset: self.scroll_position,
+        });
+        if !self.is_playing {
+            self.is_playing = true;
+            self.last_update = Instant::now();
+        }
+    }
+
+    // Stop at the end of the reading and work out the words per minute it went at, counting
+    // the words of the rows that scrolled past, and the speed that keeps up with it
+    fn finish_calibration(&mut self) {
+        let Some(Calibration::Reading {
+            started,
+            start_offset,
+        }) = self.calibration
+        else {
+            return;
+        };
+        self.is_playing = false;
+
+        let seconds = started.elapsed().as_secs_f32();
+        let words = self.words_between(start_offset, self.scroll_position);
+        self.calibration = Some(if seconds < MIN_CALIBRATION_SECONDS {
+            Calibration::Failed(format!(
+                "Read for at least {:.0} seconds to calibrate",
+                MIN_CALIBRATION_SECONDS
+            ))
+        } else if words == 0 {
+            Calibration::Failed("No words scrolled past while calibrating".to_string())
+        } else {
+            let pixels_per_second = (self.scroll_position - start_offset) / seconds;
+            Calibration::Done {
+                words_per_minute: words as f32 * 60.0 / seconds,
+                speed: self.speed_in_unit(pixels_per_second),
+            }
+        });
+    }
+
+    // Use a calibrated speed, remembering the pace for the file
+    fn accept_calibration(&mut self) {
+        let Some(Calibration::Done {
+            words_per_minute,
+            speed,
+        }) = self.calibration.take()
+        else {
+            return;
+        };
+        self.scroll_speed = self.settings.clamp_speed(speed);
+        if let Some(key) = self.current_file_key() {
+            self.settings.files.entry(key).or_default().words_per_minute = Some(words_per_minute);
+            self.save_settings();
+        }
+        self.store_file_settings();
+    }
+
+    // Number of words in the rows starting between two offsets, using the last layout
+    fn words_between(&self, start: f32, end: f32) -> usize {
+        let mut top = 0.0;
+        let mut words = 0;
+        for (block, height) in self.blocks.iter().zip(&self.row_heights) {
+            if let RenderBlock::Pause(_) = block {
+                continue;
+            }
+            if (start..end).contains(&top) {
+                words += block_text(block).map_or(0, count_words);
+            }
+            top += height + ROW_SPACING;
+        }
+        words
+    }
+
+    fn render_calibration(&mut self, ui: &mut egui::Ui) {
+        match self.calibration.clone() {
+            None => {
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            self.current_file.is_some(),
+                            egui::Button::new("Calibrate Speed"),
+                        )
+                        .on_hover_text(
+                            "Read aloud as the script scrolls, adjusting the speed to keep up, \
+                             and press C at the start and end",
+                        )
+                        .clicked()
+                    {
+                        self.start_calibration();
+                    }
+                    let words_per_minute = self
+                        .current_file_key()
+                        .and_then(|key| self.settings.files.get(&key))
+                        .and_then(|file_settings| file_settings.words_per_minute);
+                    if let Some(words_per_minute) = words_per_minute {
+                        ui.label(format!("{:.0} wpm", words_per_minute));
+                    }
+                });
+            }
+            Some(Calibration::Reading { .. }) => {
+                ui.label("Calibrating: read aloud, then press C when you stop");
+                ui.horizontal(|ui| {
+                    if ui.button("Finish").clicked() {
+                        self.finish_calibration();
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.calibration = None;
+                        self.is_playing = false;
+                    }
+                });
+            }
+            Some(Calibration::Done {
+                words_per_minute,
+                speed,
+            }) => {
+                ui.label(format!(
+                    "You read at {:.0} words per minute, a speed of {}",
+                    words_per_minute,
+                    self.settings.speed_unit.format(speed)
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Accept").clicked() {
+                        self.accept_calibration();
+                    }
+                    if ui.button("Discard").clicked() {
+                        self.calibration = None;
+                    }
+                });
+            }
+            Some(Calibration::Failed(message)) => {
+                ui.horizontal(|ui| {
+                    ui.label(message.as_str());
+                    if ui.button("Dismiss").clicked() {
+                        self.calibration = None;
+                    }
+                });
+            }
+        }
+    }
+
+    // Estimated seconds to read from one offset to another at the current speed, with the
+    // pause at the heading it starts from
+    fn reading_seconds(&self, start: f32, end: f32, heading: bool) -> Option<f32> {
+        let pixels_per_second = self.pixels_per_second();
+        if pixels_per_second <= 0.0 || end < start {
+            return None;
+        }
+        let pause = if heading && self.pause_at_headings {
+            self.heading_pause_duration
+        } else {
+            0.0
+        };
+        Some((end - start) / pixels_per_second + pause)
+    }
+
+    // Fold or unfold a heading's subsections in the outline, remembering it for the file
+    fn toggle_section(&mut self, title: &str) {
+        if let Some(index) = self.collapsed_sections.iter().position(|t| t == title) {
+            self.collapsed_sections.remove(index);
+        } else {
+            self.collapsed_sections.push(title.to_string());
+        }
+        if let Some(key) = self.current_file_key() {
+            self.settings
+                .files
+                .entry(key)
+                .or_default()
+                .collapsed_sections = self.collapsed_sections.clone();
+            self.save_settings();
+        }
+    }
+
+    // The headings with their word counts and estimated reading times, which can be clicked
+    // to jump to them. Headings with deeper ones under them fold like an editor's outline
+    fn render_outline(&mut self, ui: &mut egui::Ui) {
+        let headings: Vec<(usize, String)> = self
+            .blocks
+            .iter()
+            .filter(|block| self.is_heading(block))
+            .filter_map(|block| match block {
+                RenderBlock::Heading { level, text } => Some((*level, text.clone())),
+                _ => None,
+            })
+            .collect();
+        if headings.is_empty() {
+            return;
+        }
+        let current = self.current_section();
+        let end = self.max_scroll_position();
+        let weak = ui.visuals().weak_text_color();
+        ui.collapsing(format!("Outline ({})", headings.len()), |ui| {
+            let mut jump = None;
+            let mut toggle = None;
+            let mut folded_level = None; // headings deeper than this are in a folded section
+            let preamble_words = self.section_words.first().copied().unwrap_or(0);
+            if preamble_words > 0 {
+                let seconds = self
+                    .heading_offsets
+                    .first()
+                    .and_then(|&first| self.reading_seconds(0.0, first, false));
+                ui.colored_label(
+                    weak,
+                    format!("Opening: {}", section_summary(preamble_words, seconds)),
+                );
+            }
+            for (index, (level, title)) in headings.iter().enumerate() {
+                match folded_level {
+                    Some(folded) if *level > folded => continue,
+                    _ => folded_level = None,
+                }
+                let has_subsections = headings
+                    .get(index + 1)
+                    .is_some_and(|(next_level, _)| next_level > level);
+                let collapsed = has_subsections && self.collapsed_sections.contains(title);
+                if collapsed {
+                    folded_level = Some(*level);
+                }
+                let words = self.section_words.get(index + 1).copied().unwrap_or(0);
+                let seconds = self.heading_offsets.get(index).and_then(|&start| {
+                    let next = self.heading_offsets.get(index + 1).copied();
+                    self.reading_seconds(start, next.unwrap_or(end).max(start), true)
+                });
+                ui.horizontal(|ui| {
+                    ui.add_space((level - 1) as f32 * 12.0);
+                    let icon = if collapsed {
+                        ICON_CHEVRON_RIGHT
+                    } else {
+                        ICON_EXPAND_MORE
+                    };
+                    if ui
+                        .add_visible(has_subsections, egui::Button::new(icon).frame(false))
+                        .on_hover_text(if collapsed { "Expand" } else { "Collapse" })
+                        .clicked()
+                    {
+                        toggle = Some(title.clone());
+                    }
+                    if ui
+                        .selectable_label(current == Some(index), title)
+                        .on_hover_text("Jump to this heading")
+                        .clicked()
+                    {
+                        jump = Some(index);
+                    }
+                    ui.colored_label(weak, section_summary(words, seconds));
+                });
+            }
+            if let Some(index) = jump {
+                self.jump_to_section(index);
+            }
+            if let Some(title) = toggle {
+                self.toggle_section(&title);
+            }
+        });
+    }
+
+    fn current_file_key(&self) -> Option<String> {
+        self.current_file
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string())
+    }
+
+    fn set_marker(&mut self, slot: usize) {
+        self.markers[slot] = Some(self.scroll_position);
+        self.store_markers();
+    }
+
+    fn go_to_marker(&mut self, slot: usize) {
+        if let Some(position) = self.markers[slot] {
+            self.seek_to(position);
+        }
+    }
+
+    // The practice range's start and end, when it's on and both its markers are set in order
+    fn practice_bounds(&self) -> Option<(f32, f32)> {
+        if !self.practice_range {
+            return None;
+        }
+        let (start, end) = self.practice_markers;
+        match (self.markers[start - 1], self.markers[end - 1]) {
+            (Some(start), Some(end)) if start < end => Some((start, end)),
+            _ => None,
+        }
+    }
+
+    // Where playback starts and ends: the practice range, or the whole script
+    fn playback_bounds(&self) -> (f32, f32) {
+        self.practice_bounds()
+            .unwrap_or((0.0, self.max_scroll_position()))
+    }
+
+    // What playback does at the end: kiosk mode always loops, so the display never stops
+    fn end_behavior(&self) -> EndBehavior {
+        if self.settings.kiosk_mode {
+            EndBehavior::Loop
+        } else {
+            self.settings.end_behavior
+        }
+    }
+
+    // Seconds until kiosk mode starts playing the loaded file, if it's waiting to
+    fn kiosk_countdown(&self) -> Option<f32> {
+        let loaded = self.kiosk_start?;
+        Some((self.settings.kiosk_start_delay - loaded.elapsed().as_secs_f32()).max(0.0))
+    }
+
+    // Start playing once the kiosk delay is up. Playing or closing the file before then
+    // takes over from the countdown
+    fn check_kiosk_start(&mut self, ctx: &egui::Context) {
+        if self.is_playing || self.content.is_empty() {
+            self.kiosk_start = None;
+        }
+        let Some(remaining) = self.kiosk_countdown() else {
+            return;
+        };
+        if remaining <= 0.0 {
+            self.kiosk_start = None;
+            self.is_playing = true;
+            self.last_update = Instant::now();
+            self.enter_practice_range();
+        } else {
+            ctx.request_repaint_after(Duration::from_secs_f32(remaining.min(1.0)));
+        }
+    }
+
+    // Playing from outside the practice range starts from its beginning
+    fn enter_practice_range(&mut self) {
+        if let Some((start, end)) = self.practice_bounds() {
+            if !(start..end).contains(&self.scroll_position) {
+                self.seek_to(start);
+            }
+        }
+    }
+
+    // Markers are remembered on their own or as part of per-file settings
+    fn remembers_markers(&self) -> bool {
+        self.settings.persist_markers || self.settings.per_file_settings
+    }
+
+    // Save the current file's markers to settings if marker persistence is enabled
+    fn store_markers(&mut self) {
+        if !self.remembers_markers() {
+            return;
+        }
+        if let Some(key) = self.current_file_key() {
+            let markers = self
+                .markers
+                .iter()
+                .enumerate()
+                .filter_map(|(slot, position)| position.map(|position| Marker { slot, position }))
+                .collect();
+            self.settings.files.entry(key).or_default().markers = markers;
+            self.save_settings();
+        }
+    }
+
+    // Load the current file's saved markers, or clear them
+    fn restore_markers(&mut self) {
+        self.markers = [None; MARKER_COUNT];
+        if !self.remembers_markers() {
+            return;
+        }
+        if let Some(file_settings) = self
+            .current_file_key()
+            .and_then(|key| self.settings.files.get(&key))
+        {
+            for marker in &file_settings.markers {
+                if marker.slot < MARKER_COUNT {
+                    self.markers[marker.slot] = Some(marker.position);
+                }
+            }
+        }
+    }
+
+    // Remember where reading stopped in the current file. Finished files start from the top
+    // next time
+    fn store_resume_position(&mut self) {
+        if !self.settings.resume_files || self.content_height <= 0.0 {
+            return;
+        }
+        let Some(key) = self.current_file_key() else {
+            return;
+        };
+        let position = Some(self.scroll_position / self.content_height)
+            .filter(|_| self.scroll_position > 0.0 && self.progress_percent() < 100.0);
+        let entry = self.settings.files.entry(key).or_default();
+        if entry.resume_position != position {
+            entry.resume_position = position;
+            self.save_settings();
+        }
+    }
+
+    // Go back to where reading stopped in the current file, once it has been laid out
+    fn restore_resume_position(&mut self) {
+        self.resumed = false;
+        if !self.settings.resume_files {
+            return;
+        }
+        let position = self
+            .current_file_key()
+            .and_then(|key| self.settings.files.get(&key))
+            .and_then(|file_settings| file_settings.resume_position)
+            .filter(|position| position.is_finite() && *position > 0.0);
+        if let Some(position) = position {
+            self.scroll_fraction = Some(position.min(1.0));
+            self.resumed = true;
+        }
+    }
+
+    // Banner above the content after resuming, offering to start from the top instead
+    fn render_resume_banner(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Resumed at {:.0}%",
+                self.progress_percent().min(100.0)
+            ));
+            if ui.button("Start Over").clicked() {
+                self.seek_to(0.0);
+                self.resumed = false;
+            }
+            if ui.button("Dismiss").clicked() {
+                self.resumed = false;
+            }
+        });
+    }
+
+    // The speed, font size and theme a file gets when it has no saved settings
+    fn file_defaults(&self) -> (f32, f32, String) {
+        (
+            self.settings.default_scroll_speed,
+            self.settings.default_font_size,
+            self.default_theme.clone(),
+        )
+    }
+
+    // Apply the current file's saved speed, font size and theme, or the global defaults
+    fn apply_file_settings(&mut self) {
+        if !self.settings.per_file_settings {
+            return;
+        }
+
+        let (default_speed, default_font_size, default_theme) = self.file_defaults();
+        let saved = self
+            .current_file_key()
+            .and_then(|key| self.settings.files.get(&key))
+            .cloned()
+            .unwrap_or_default();
+
+        self.scroll_speed = self
+            .settings
+            .clamp_speed(saved.scroll_speed.unwrap_or(default_speed));
+        self.font_size = saved
+            .font_size
+            .unwrap_or(default_font_size)
+            .clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+
+        let theme_name = self
+            .theme_override
+            .clone()
+            .or(saved.theme)
+            .unwrap_or(default_theme);
+        if let Some(theme) = self.available_themes.iter().find(|t| t.name == theme_name) {
+            self.current_theme = theme.clone();
+        }
+    }
+
+    // Remember the current file's speed, font size and theme once they differ from what
+    // it would otherwise get
+    fn store_file_settings(&mut self) {
+        if !self.settings.per_file_settings {
+            return;
+        }
+        let Some(key) = self.current_file_key() else {
+            return;
+        };
+
+        let (default_speed, default_font_size, default_theme) = self.file_defaults();
+        let saved = self.settings.files.get(&key);
+        let saved_speed = saved.and_then(|f| f.scroll_speed).unwrap_or(default_speed);
+        let saved_font_size = saved.and_then(|f| f.font_size).unwrap_or(default_font_size);
+        let saved_theme = saved.and_then(|f| f.theme.clone()).unwrap_or(default_theme);
+        // A theme from the command line isn't remembered for the file
+        let theme = match self.theme_override {
+            Some(_) => saved_theme.clone(),
+            None => self.current_theme.name.clone(),
+        };
+
+        if saved_speed != self.scroll_speed
+            || saved_font_size != self.font_size
+            || saved_theme != theme
+        {
+            let entry = self.settings.files.entry(key).or_default();
+            entry.scroll_speed = Some(self.scroll_speed);
+            entry.font_size = Some(self.font_size);
+            entry.theme = Some(theme);
+            self.save_settings();
+        }
+    }
+
+    // Make the current speed, font size and theme the defaults for files without settings
+    fn use_as_default(&mut self) {
+        self.settings.default_scroll_speed = self.scroll_speed;
+        self.settings.default_font_size = self.font_size;
+        self.default_theme = self.current_theme.name.clone();
+        self.theme_override = None;
+        if let Err(e) = save_theme_preference(&self.default_theme) {
+            eprintln!("Failed to save theme preference: {}", e);
+        }
+        self.save_settings();
+    }
+
+    // Forget everything saved for the current file and go back to the defaults
+    fn clear_file_settings(&mut self) {
+        if let Some(key) = self.current_file_key() {
+            self.settings.files.remove(&key);
+            self.save_settings();
+            self.restore_markers();
+            self.apply_file_settings();
+        }
+    }
+
+    fn apply_speed_preset(&mut self, index: usize) {
+        self.scroll_speed = self
+            .settings
+            .clamp_speed(self.settings.speed_presets[index]);
+    }
+
+    // Move to the preset after the one currently in use, starting from the first
+    fn cycle_speed_preset(&mut self) {
+        let next = self
+            .settings
+            .speed_presets
+            .iter()
+            .position(|&preset| preset == self.scroll_speed)
+            .map_or(0, |current| (current + 1) % SPEED_PRESET_NAMES.len());
+        self.apply_speed_preset(next);
+    }
+
+    fn handle_keyboard(&mut self, ctx: &egui::Context) {
+        // Leave keys alone while a text or number field is being edited
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        const MARKER_KEYS: [egui::Key; MARKER_COUNT] = [
+            egui::Key::Num1,
+            egui::Key::Num2,
+            egui::Key::Num3,
+            egui::Key::Num4,
+            egui::Key::Num5,
+            egui::Key::Num6,
+            egui::Key::Num7,
+            egui::Key::Num8,
+            egui::Key::Num9,
+        ];
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::P)) {
+            self.cycle_speed_preset();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::C)) {
+            match self.calibration {
+                Some(Calibration::Reading { .. }) => self.finish_calibration(),
+                _ => self.start_calibration(),
+            }
+        }
+
+        // Arrow keys seek and change speed, in steps scaled by Shift and Ctrl/Cmd
+        let modifiers = ctx.input(|i| i.modifiers);
+        let pressed = |key| ctx.input_mut(|i| i.consume_key(modifiers, key));
+        let seek_step = keyboard_step(modifiers, KEYBOARD_SEEK_STEP);
+        let speed_step = keyboard_step(modifiers, self.settings.scroll_speed_step);
+        if pressed(egui::Key::ArrowDown) {
+            self.scroll_by(seek_step);
+        }
+        if pressed(egui::Key::ArrowUp) {
+            self.scroll_by(-seek_step);
+        }
+        if pressed(egui::Key::ArrowRight) {
+            self.scroll_speed = self.settings.clamp_speed(self.scroll_speed + speed_step);
+        }
+        if pressed(egui::Key::ArrowLeft) {
+            self.scroll_speed = self.settings.clamp_speed(self.scroll_speed - speed_step);
+        }
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::PageDown)) {
+            self.scroll_by(self.viewport_height);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::PageUp)) {
+            self.scroll_by(-self.viewport_height);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::CloseBracket)) {
+            self.jump_to_heading(true);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::OpenBracket)) {
+            self.jump_to_heading(false);
+        }
+
+        // Alt+0-9 jumps to 0-90% of the way through the script
+        for (tenths, key) in [egui::Key::Num0].into_iter().chain(MARKER_KEYS).enumerate() {
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::ALT, key)) {
+                self.jump_to_percent(tenths as f32 * 10.0);
+            }
+        }
+
+        for (slot, key) in MARKER_KEYS.into_iter().enumerate() {
+            // Ctrl/Cmd+number sets a marker, the number alone jumps to it
+            if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, key)) {
+                self.set_marker(slot);
+            } else if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, key)) {
+                self.go_to_marker(slot);
+            }
+        }
+    }
+
+    // Scale the whole interface, text styles and controls alike. The display's own scale is
+    // only known once the window is up, so the first run picks it as the default then
+    fn apply_ui_scale(&mut self, ctx: &egui::Context) {
+        if self.ui_scale_applied {
+            return;
+        }
+        let Some(native) = ctx.native_pixels_per_point() else {
+            return;
+        };
+        let scale = match self.settings.ui_scale {
+            Some(scale) => scale,
+            None => {
+                let scale = native.clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+                eprintln!("Using the display's UI scale of {}", scale);
+                self.settings.ui_scale = Some(scale);
+                self.save_settings();
+                scale
+            }
+        };
+        ctx.set_pixels_per_point(scale);
+        self.ui_scale_applied = true;
+    }
+
+    fn set_ui_scale(&mut self, scale: f32) {
+        self.settings.ui_scale = Some(scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE));
+        self.ui_scale_applied = false;
+        self.save_settings();
+    }
+
+    // Keep track of the window's geometry to save on exit. On the first frame after restoring
+    // a position, move the window back to the middle of the screen if it came up off screen,
+    // as when the monitor it was on has been disconnected
+    fn track_window(&mut self, ctx: &egui::Context) {
+        let viewport = ctx.input(|i| i.viewport().clone());
+        let (Some(outer), Some(inner), Some(monitor)) = (
+            viewport.outer_rect,
+            viewport.inner_rect,
+            viewport.monitor_size,
+        ) else {
+            return;
+        };
+
+        if self.window_check_pending {
+            self.window_check_pending = false;
+            // Only the current monitor's size is known, so a window beyond it is only taken
+            // to be off screen when the monitor isn't the one it was saved on
+            let on_screen = outer.intersects(egui::Rect::from_min_size(egui::Pos2::ZERO, monitor));
+            let saved_monitor = self.settings.window.and_then(|window| window.monitor_size);
+            if !on_screen && saved_monitor != Some([monitor.x, monitor.y]) {
+                let centered = ((monitor - outer.size()) / 2.0).max(egui::Vec2::ZERO);
+                ctx.send_viewport_cmd(egui::ViewportCommand::OuterPosition(centered.to_pos2()));
+                return;
+            }
+        }
+
+        let normal = !viewport.minimized.unwrap_or(false)
+            && !viewport.maximized.unwrap_or(false)
+            && !viewport.fullscreen.unwrap_or(false);
+        if normal {
+            self.window_geometry = Some(WindowGeometry {
+                position: [outer.min.x, outer.min.y],
+                size: [inner.width(), inner.height()],
+                monitor_size: Some([monitor.x, monitor.y]),
+            });
+        }
+    }
+
+    // Opacity of the controls panel: shown while paused, and while playing until a delay
+    // after playback starts or the pointer was last moved near the left edge or over them
+    fn controls_opacity(&mut self, ctx: &egui::Context) -> f32 {
+        let shown = self.controls_opacity_target(ctx);
+        ctx.animate_bool_with_time(egui::Id::new("controls_panel"), shown, CONTROLS_FADE_TIME)
+    }
+
+    // A thin full-height strip with a chevron that folds the controls panel away or brings
+    // it back, keeping the reading position as the content takes up the new width
+    fn render_controls_toggle(&mut self, ui: &mut egui::Ui, opacity: f32) {
+        let (icon, hint) = if self.settings.controls_collapsed {
+            (ICON_CHEVRON_RIGHT, "Show the controls")
+        } else {
+            (ICON_CHEVRON_LEFT, "Hide the controls")
+        };
+        ui.scope(|ui| {
+            ui.multiply_opacity(opacity);
+            if ui
+                .add_sized(
+                    [CONTROLS_TOGGLE_WIDTH, ui.available_height()],
+                    egui::Button::new(icon).frame(false),
+                )
+                .on_hover_text(hint)
+                .clicked()
+            {
+                self.settings.controls_collapsed = !self.settings.controls_collapsed;
+                self.preserve_scroll_fraction();
+                self.save_settings();
+            }
+        });
+    }
+
+    fn controls_opacity_target(&mut self, ctx: &egui::Context) -> bool {
+        if !self.settings.auto_hide_controls || !self.is_playing {
+            self.controls_wanted = Instant::now();
+            return true;
+        }
+        let hidden =
+            self.controls_wanted.elapsed().as_secs_f32() > self.settings.controls_hide_delay;
+        let reach = if hidden {
+            self.settings.controls_reveal_width
+        } else if self.settings.controls_collapsed {
+            CONTROLS_TOGGLE_WIDTH
+        } else {
+            self.settings.controls_width
+        };
+        let (moved, pointer) =
+            ctx.input(|i| (i.pointer.delta() != egui::Vec2::ZERO, i.pointer.hover_pos()));
+        if moved && pointer.is_some_and(|pos| pos.x <= reach) {
+            self.controls_wanted = Instant::now();
+        }
+        let shown =
+            self.controls_wanted.elapsed().as_secs_f32() <= self.settings.controls_hide_delay;
+        if shown {
+            // Look again once the delay is up, even if nothing else repaints
+            ctx.request_repaint_after(Duration::from_secs_f32(self.settings.controls_hide_delay));
+        }
+        shown
+    }
+
+    // Keep the cursor off the text for recordings. Called after the UI is laid out, so it
+    // overrides any cursor set by the widgets
+    fn update_cursor_visibility(&mut self, ctx: &egui::Context) {
+        let pointer_active =
+            ctx.input(|i| i.pointer.delta() != egui::Vec2::ZERO || i.pointer.any_down());
+        if pointer_active || !self.is_playing {
+            self.last_pointer_activity = Instant::now();
+        }
+        if self.settings.hide_cursor
+            && self.last_pointer_activity.elapsed().as_secs_f32() > CURSOR_HIDE_DELAY
+        {
+            ctx.set_cursor_icon(egui::CursorIcon::None);
+        }
+    }
+
+    // egui snaps text to whole pixels for sharpness, so slow scrolling moves it in uneven one
+    // pixel steps. While playing with subpixel scrolling on, text is drawn at its exact position
+    // instead, which is very slightly softer but glides
+    fn update_text_rounding(&self, ctx: &egui::Context) {
+        let round = !(self.settings.subpixel_scrolling && self.is_playing);
+        if ctx.tessellation_options(|options| options.round_text_to_pixels) != round {
+            ctx.tessellation_options_mut(|options| options.round_text_to_pixels = round);
+        }
+    }
+
+    // Turn mouse wheel movement into speed steps, one per notch, and keep it from scrolling
+    // anything else. Touchpads move in smaller amounts, which add up to notches
+    fn take_wheel_for_speed(&mut self, ctx: &egui::Context) {
+        let delta = ctx.input_mut(|i| {
+            let delta = i.raw_scroll_delta.y;
+            i.raw_scroll_delta = egui::Vec2::ZERO;
+            i.smooth_scroll_delta = egui::Vec2::ZERO;
+            delta
+        });
+        if delta == 0.0 {
+            return;
+        }
+        self.wheel_speed_delta += delta;
+        let notches = (self.wheel_speed_delta / WHEEL_NOTCH).trunc();
+        if notches != 0.0 {
+            self.wheel_speed_delta -= notches * WHEEL_NOTCH;
+            self.scroll_speed = self
+                .settings
+                .clamp_speed(self.scroll_speed + notches * self.settings.scroll_speed_step);
+            self.speed_readout = Some(Instant::now());
+        }
+    }
+
+    fn handle_focus(&mut self, focused: bool) {
+        if !self.settings.pause_on_focus_loss {
+            self.paused_by_focus_loss = false;
+            return;
+        }
+
+        if !focused && self.is_playing {
+            self.is_playing = false;
+            self.paused_by_focus_loss = true;
+        } else if focused && self.paused_by_focus_loss {
+            self.paused_by_focus_loss = false;
+            if self.settings.resume_on_focus {
+                self.is_playing = true;
+                self.last_update = Instant::now();
+            }
+        }
+    }
+
+    fn pixels_per_second(&self) -> f32 {
+        match self.settings.speed_unit {
+            SpeedUnit::PixelsPerSecond => self.scroll_speed,
+            SpeedUnit::LinesPerSecond => self.scroll_speed * self.font_size * LINE_HEIGHT_FACTOR,
+        }
+    }
+
+    // A speed in pixels per second, in settings.speed_unit at the current font size
+    fn speed_in_unit(&self, pixels_per_second: f32) -> f32 {
+        match self.settings.speed_unit {
+            SpeedUnit::PixelsPerSecond => pixels_per_second,
+            SpeedUnit::LinesPerSecond => pixels_per_second / (self.font_size * LINE_HEIGHT_FACTOR),
+        }
+    }
+
+    // The speed that gets from the top to the end of the script in the given time, after
+    // the pauses on the way, measured from the last layout so it follows reloads
+    fn speed_for_duration(&self, seconds: f32) -> Option<f32> {
+        let scrolling_seconds = seconds - self.total_pause_seconds();
+        let distance = self.max_scroll_position();
+        if distance <= 0.0 || scrolling_seconds <= 0.0 {
+            return None;
+        }
+        Some(self.speed_in_unit(distance / scrolling_seconds))
+    }
+
+    // How long playback spends paused over the whole script with the current pause settings,
+    // counting a paragraph break only where a heading or cue doesn't pause instead
+    fn total_pause_seconds(&self) -> f32 {
+        let heading_pauses = if self.pause_at_headings {
+            self.heading_offsets.len() as f32 * self.heading_pause_duration
+        } else {
+            0.0
+        };
+        let cue_pauses: f32 = self.pause_cues.iter().map(|&(_, seconds)| seconds).sum();
+        let paragraph_pauses = if self.pause_at_paragraphs {
+            let breaks = self
+                .paragraph_offsets
+                .iter()
+                .filter(|offset| {
+                    let paused_there = (self.pause_at_headings
+                        && self.heading_offsets.contains(offset))
+                        || self.pause_cues.iter().any(|(cue, _)| cue == *offset);
+                    !paused_there
+                })
+                .count();
+            breaks as f32 * self.paragraph_pause_duration
+        } else {
+            0.0
+        };
+        heading_pauses + cue_pauses + paragraph_pauses
+    }
+
+    // Pick a time to finish the script in and apply the speed that takes
+    fn render_target_duration(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Finish in:");
+            ui.add(
+                egui::DragValue::new(&mut self.target_minutes)
+                    .range(0.1..=MAX_TARGET_MINUTES)
+                    .speed(0.1)
+                    .max_decimals(1)
+                    .suffix(" min"),
+            );
+            match self.speed_for_duration(self.target_minutes * 60.0) {
+                Some(speed) => {
+                    let clamped = self.settings.clamp_speed(speed);
+                    let hover = if clamped != speed {
+                        format!(
+                            "Finishing in this time takes {}, beyond the speed limits",
+                            self.settings.speed_unit.format(speed)
+                        )
+                    } else {
+                        "Set the speed that finishes the script in this time".to_string()
+                    };
+                    if ui
+                        .button(format!("Use {}", self.settings.speed_unit.format(clamped)))
+                        .on_hover_text(hover)
+                        .clicked()
+                    {
+                        self.scroll_speed = clamped;
+                    }
+                }
+                None if self.max_scroll_position() > 0.0 => {
+                    ui.label("Too short for the pauses");
+                }
+                None => {}
+            }
+        });
+    }
+
+    // Switch speed units, converting every speed to the new unit at the current font size
+    fn set_speed_unit(&mut self, unit: SpeedUnit) {
+        let line_pixels = self.font_size * LINE_HEIGHT_FACTOR;
+        let scale = match (self.settings.speed_unit, unit) {
+            (SpeedUnit::PixelsPerSecond, SpeedUnit::LinesPerSecond) => 1.0 / line_pixels,
+            (SpeedUnit::LinesPerSecond, SpeedUnit::PixelsPerSecond) => line_pixels,
+            _ => return,
+        };
+        let settings = &mut self.settings;
+        settings.speed_unit = unit;
+        settings.min_scroll_speed *= scale;
+        settings.max_scroll_speed *= scale;
+        settings.scroll_speed_step *= scale;
+        settings.default_scroll_speed *= scale;
+        for preset in &mut settings.speed_presets {
+            *preset *= scale;
+        }
+        for speed in settings
+            .files
+            .values_mut()
+            .filter_map(|f| f.scroll_speed.as_mut())
+        {
+            *speed *= scale;
+        }
+        settings.validate();
+        self.scroll_speed = self.settings.clamp_speed(self.scroll_speed * scale);
+        self.save_settings();
+    }
+
+    fn update_scroll(&mut self, dt: f32) {
+        if !self.is_playing {
+            return;
+        }
+        self.cue_log_elapsed += dt;
+
+        // Handle heading pause if enabled
+        if let Some(remaining) = self.current_pause {
+            if remaining > 0.0 {
+                self.current_pause = Some(remaining - dt);
+                return;
+            } else {
+                self.current_pause = None;
+            }
+        }
+
+        // Rise through any lead-in space before the content itself moves
+        if self.lead_in > 0.0 {
+            self.lead_in = (self.lead_in - self.pixels_per_second() * dt).max(0.0);
+            return;
+        }
+
+        // Calculate new scroll position
+        let direction = if self.reversing { -1.0 } else { 1.0 };
+        self.scroll_position += direction * self.pixels_per_second() * dt;
+        self.log_passed_headings();
+
+        // Check if we should pause at a heading
+        if self.pause_at_headings && !self.heading_offsets.is_empty() {
+            // Check if we're approaching a heading
+            for (idx, &heading_offset) in self
+                .heading_offsets
+                .iter()
+                .enumerate()
+                .skip(self.last_checked_heading_idx)
+            {
+                // If we've scrolled past the top of this heading
+                if self.scroll_position >= heading_offset && idx >= self.last_checked_heading_idx {
+                    // Pause scrolling for the specified duration
+                    self.current_pause = Some(self.heading_pause_duration);
+                    self.heading_pause = true;
+                    self.last_checked_heading_idx = idx + 1;
+                    return;
+                }
+            }
+        }
+
+        // A breath at each paragraph break, unless a heading or cue there pauses instead
+        if let Some(&offset) = self.paragraph_offsets.get(self.last_checked_paragraph_idx) {
+            if self.scroll_position >= offset {
+                self.last_checked_paragraph_idx += 1;
+                let paused_there = (self.pause_at_headings
+                    && self.heading_offsets.contains(&offset))
+                    || self.pause_cues.iter().any(|&(cue, _)| cue == offset);
+                if self.pause_at_paragraphs && !paused_there {
+                    self.current_pause = Some(self.paragraph_pause_duration);
+                    self.heading_pause = false;
+                    return;
+                }
+            }
+        }
+
+        // Pause cues in the text are always honored, for as long as they say
+        if let Some(&(offset, seconds)) = self.pause_cues.get(self.last_checked_cue_idx) {
+            if self.scroll_position >= offset {
+                self.current_pause = Some(seconds);
+                self.heading_pause = false;
+                self.last_checked_cue_idx += 1;
+            }
+        }
+    }
+}
+
+// Index of the first offset that hasn't been passed at a position: the first at or below it,
+// so one right at the position, like a heading at the very top, is still to come
+fn next_offset_idx(offsets: &[f32], position: f32) -> usize {
+    offsets.partition_point(|&offset| offset < position)
+}
+
+// Difference from the position playback set, in points, beyond which the ScrollArea was
+// moved by other input
+const SCROLL_ADOPT_TOLERANCE: f32 = 0.5;
+
+// Extra height laid out above and below the viewport so rows are ready before they scroll in
+const OFFSCREEN_BUFFER: f32 = 400.0;
+
+// Distance the Up and Down arrow keys move the content
+const KEYBOARD_SEEK_STEP: f32 = 40.0;
+
+// Shift makes keyboard steps ten times larger and Ctrl/Cmd ten times smaller, whatever
+// unit the step is in
+fn keyboard_step(modifiers: egui::Modifiers, step: f32) -> f32 {
+    if modifiers.shift {
+        step * 10.0
+    } else if modifiers.command {
+        step / 10.0
+    } else {
+        step
+    }
+}
+
+// Distance before a heading pause at which the heading starts to be highlighted
+const HEADING_APPROACH_DISTANCE: f32 = 150.0;
+
+// Strongest tint behind a heading that's about to pause or is pausing
+const HEADING_HIGHLIGHT_ALPHA: f32 = 0.12;
+
+// Seconds the mouse has to be still during playback before the cursor is hidden
+const CURSOR_HIDE_DELAY: f32 = 3.0;
+
+// Bounds for the width of the controls panel, and the longest configurable wait before it
+// hides
+const MIN_CONTROLS_WIDTH: f32 = 200.0;
+const MAX_CONTROLS_WIDTH: f32 = 600.0;
+const MAX_CONTROLS_HIDE_DELAY: f32 = 30.0;
+
+// Width of the strip with the chevron that collapses and expands the controls panel
+const CONTROLS_TOGGLE_WIDTH: f32 = 16.0;
+
+// Seconds the controls panel takes to fade in or out
+const CONTROLS_FADE_TIME: f32 = 0.3;
+
+// Wheel movement, in points, that makes one speed step
+const WHEEL_NOTCH: f32 = 50.0;
+
+// Seconds the speed stays on screen after it's changed with the wheel
+const SPEED_READOUT_DURATION: f32 = 1.5;
+
+// Seconds new content takes to fade in, and the most of a frame's time counted towards it
+const FADE_IN_DURATION: f32 = 0.3;
+const MAX_FADE_STEP: f32 = 0.05;
+
+// Seconds a theme change takes to blend into the new background and text colors
+const THEME_TRANSITION_TIME: f32 = 0.3;
+
+// Seconds the "End of script" notice stays up, the last of them fading out
+const END_NOTICE_DURATION: f32 = 3.0;
+
+// Space between the columns of the two-column layout
+const COLUMN_GAP: f32 = 32.0;
+
+// Space between the line numbers and the content
+const LINE_NUMBER_GAP: f32 = 12.0;
+
+// Vertical space between content rows
+const ROW_SPACING: f32 = 5.0;
+
+// A block of the markdown document, parsed once per content change and rendered as one row
+#[derive(Debug, Clone, PartialEq)]
+enum RenderBlock {
+    Heading {
+        level: usize,
+        text: String,
+    },
+    Paragraph(String),
+    Term(String),       // a line with ": definition" lines under it
+    Definition(String), // one of those, without the ": "
+    ListItem {
+        depth: usize,   // 0 for top-level items, counting up with each list it's nested in
+        marker: String, // "-", "*", "+", or the number and "." or ")" as written
+        text: String,
+    },
+    IndentedCode(String),
+    CodeBlock {
+        language: String,
+        code: String,
+    },
+    Alert {
+        kind: AlertKind,
+        text: String, // the quoted lines after the "[!KIND]" line, without their ">"s
+    },
+    Rule,
+    Image {
+        alt: String,
+        path: String,
+    }, // a line that's only an image, shown as a banner
+    Pause(f32),   // inline pause cue, in seconds
+    Note(String), // <!-- comment -->, only kept when notes are shown
+    Gap,          // one or more blank lines between paragraphs
+}
+
+impl RenderBlock {
+    // Rough height used for blocks that haven't been laid out yet
+    fn estimated_height(&self, font_size: f32) -> f32 {
+        let line_height = font_size * LINE_HEIGHT_FACTOR;
+        match self {
+            RenderBlock::Heading { level, .. } => line_height * HEADING_SIZE_MULTIPLIERS[level - 1],
+            RenderBlock::Paragraph(_)
+            | RenderBlock::Term(_)
+            | RenderBlock::Definition(_)
+            | RenderBlock::ListItem { .. }
+            | RenderBlock::Note(_) => line_height,
+            RenderBlock::IndentedCode(_) => line_height * 0.9,
+            RenderBlock::CodeBlock { code, .. } => line_height * 0.9 * code.lines().count() as f32,
+            RenderBlock::Alert { text, .. } => line_height * (2 + text.lines().count()) as f32,
+            RenderBlock::Image { .. } => line_height * 4.0,
+            RenderBlock::Rule | RenderBlock::Pause(_) | RenderBlock::Gap => 0.0,
+        }
+    }
+}
+
+// Font and spacing measurements the content rows are laid out with
+struct LayoutMetrics {
+    font_size: f32,
+    min_row_height: f32,
+    paragraph_gap: f32,    // in multiples of the font size
+    heading_levels: usize, // deeper headings are shown as regular text
+}
+
+impl LayoutMetrics {
+    // Height of a row: as measured when it was last laid out, or else estimated
+    fn row_height(&self, block: &RenderBlock, measured: Option<f32>) -> f32 {
+        match block {
+            RenderBlock::Pause(_) => 0.0,
+            RenderBlock::Gap => self.paragraph_gap * self.font_size,
+            _ => measured
+                .unwrap_or_else(|| block.estimated_height(self.font_size))
+                .max(self.min_row_height),
+        }
+    }
+}
+
+// Heights of the content rows, and where the headings and pause cues fall, measured from
+// the top of the content like scroll_position
+#[derive(Debug, Default)]
+struct BlockLayout {
+    heights: Vec<f32>,
+    heading_offsets: Vec<f32>,
+    pause_cues: Vec<(f32, f32)>, // offset and seconds
+    paragraph_offsets: Vec<f32>, // rows after blank lines, other than at the very top
+}
+
+// Stack the rows with the given measured heights, estimating any that haven't been laid out.
+// This needs no window, so the layout can be checked without one
+fn layout_blocks(blocks: &[RenderBlock], measured: &[f32], metrics: &LayoutMetrics) -> BlockLayout {
+    let mut layout = BlockLayout::default();
+    let mut top = 0.0;
+    let mut after_gap = false;
+    for (index, block) in blocks.iter().enumerate() {
+        let height = metrics.row_height(block, measured.get(index).copied());
+        // The first row after blank lines starts a paragraph. Cues in between don't count
+        match block {
+            RenderBlock::Pause(_) => {}
+            RenderBlock::Gap => after_gap = top > 0.0,
+            _ if after_gap => {
+                layout.paragraph_offsets.push(top);
+                after_gap = false;
+            }
+            _ => {}
+        }
+        match block {
+            // Cues take up no space, not even the spacing between rows
+            RenderBlock::Pause(seconds) => {
+                layout.pause_cues.push((top, *seconds));
+                layout.heights.push(height);
+                continue;
+            }
+            RenderBlock::Heading { level, .. } if *level <= metrics.heading_levels => {
+                layout.heading_offsets.push(top);
+            }
+            _ => {}
+        }
+        layout.heights.push(height);
+        top += height + ROW_SPACING;
+    }
+    layout
+}
+
+// Split markdown source into render blocks: one per heading, rule, line of text or line of
+// indented code, one per fenced code block and one per run of blank lines. Each comes with
+// the 1-based source line it starts on. Without newlines_as_breaks, consecutive lines of text
+// are joined into one paragraph as in standard markdown. HTML comments are dropped, or kept
+// as notes with show_notes
+fn parse_blocks(
+    content: &str,
+    newlines_as_breaks: bool,
+    show_notes: bool,
+) -> Vec<(usize, RenderBlock)> {
+    let mut blocks = Vec::new();
+    let mut line_iter = content.lines().enumerate().peekable();
+    let mut previous_blank = true;
+    let mut in_code_block = false;
+    // Index of the alert block that quoted lines are added to, while in one
+    let mut alert_idx: Option<usize> = None;
+    // Column the text of each list item the current line could be nested in starts at
+    let mut list_columns: Vec<usize> = Vec::new();
+    while let Some((index, line)) = line_iter.next() {
+        let line_number = index + 1;
+        let trimmed = line.trim();
+        let expanded = expand_tabs(line);
+        let after_blank = previous_blank;
+        previous_blank = trimmed.is_empty();
+        // Any line that isn't quoted ends an alert, code included
+        if !trimmed.starts_with('>') {
+            alert_idx = None;
+        }
+
+        // Four-space indented blocks are code, as long as they don't continue a paragraph. In
+        // a list, that's four spaces past where the item's text starts
+        if !trimmed.is_empty() {
+            let indent = expanded.len() - expanded.trim_start_matches(' ').len();
+            let code_column = list_columns.last().copied().unwrap_or(0) + CODE_INDENT.len();
+            in_code_block = indent >= code_column && (after_blank || in_code_block);
+        }
+        if in_code_block && !trimmed.is_empty() {
+            blocks.push((
+                line_number,
+                RenderBlock::IndentedCode(expanded[CODE_INDENT.len()..].to_string()),
+            ));
+            continue;
+        }
+
+        // Fenced code blocks run until a matching closing fence, or the end of the document
+        if let Some((fence, language)) = code_fence(trimmed) {
+            let code = line_iter
+                .by_ref()
+                .take_while(|(_, code_line)| !is_closing_fence(code_line.trim(), fence))
+                .map(|(_, code_line)| expand_tabs(code_line))
+                .collect::<Vec<_>>()
+                .join("\n");
+            blocks.push((
+                line_number,
+                RenderBlock::CodeBlock {
+                    language: language.to_string(),
+                    code,
+                },
+            ));
+            continue;
+        }
+
+        // A comment that starts a line and isn't closed on it takes in the lines up to its end.
+        // One anywhere else is text, so a stray "<!--" can't hide the rest of the script
+        let mut expanded = expanded;
+        while trimmed.starts_with("<!--") && has_unclosed_comment(&expanded) {
+            let Some((_, next)) = line_iter.next() else {
+                break;
+            };
+            expanded.push('\n');
+            expanded.push_str(&expand_tabs(next));
+        }
+
+        // Pause cues are taken out of the text, and a line with nothing else isn't shown
+        let (expanded, pauses) = extract_pause_cues(&expanded);
+        if !pauses.is_empty() {
+            blocks.extend(
+                pauses
+                    .into_iter()
+                    .map(|seconds| (line_number, RenderBlock::Pause(seconds))),
+            );
+            if expanded.trim().is_empty() {
+                previous_blank = after_blank;
+                continue;
+            }
+        }
+
+        // So are other comments, which are author notes
+        let (expanded, notes) = extract_notes(&expanded);
+        if !notes.is_empty() {
+            if show_notes {
+                blocks.extend(
+                    notes
+                        .into_iter()
+                        .map(|note| (line_number, RenderBlock::Note(note))),
+                );
+            }
+            if expanded.trim().is_empty() {
+                previous_blank = after_blank;
+                continue;
+            }
+        }
+        let trimmed = expanded.trim();
+
+        // A "> [!NOTE]" line starts an alert, taking in the quoted lines below it. Blockquotes
+        // of any other kind are left as they're written
+        match (trimmed.strip_prefix('>'), alert_idx) {
+            (Some(quoted), Some(index)) => {
+                if let (_, RenderBlock::Alert { text, .. }) = &mut blocks[index] {
+                    // Lines are joined like a paragraph's, and a bare ">" separates paragraphs
+                    let quoted = quoted.trim();
+                    let separator = if text.is_empty() || text.ends_with('\n') {
+                        ""
+                    } else if quoted.is_empty() {
+                        "\n\n"
+                    } else if newlines_as_breaks || ends_with_hard_break(text) {
+                        let kept = text.trim_end_matches('\\').trim_end().len();
+                        text.truncate(kept);
+                        "\n"
+                    } else {
+                        " "
+                    };
+                    text.push_str(separator);
+                    text.push_str(quoted);
+                }
+                continue;
+            }
+            (Some(quoted), None) => {
+                if let Some(kind) = AlertKind::parse(quoted) {
+                    alert_idx = Some(blocks.len());
+                    blocks.push((
+                        line_number,
+                        RenderBlock::Alert {
+                            kind,
+                            text: String::new(),
+                        },
+                    ));
+                    list_columns.clear();
+                    continue;
+                }
+            }
+            (None, _) => {}
+        }
+
+        // A run of blank lines becomes a single gap, however many lines it spans
+        if trimmed.is_empty() {
+            let last_shown = blocks
+                .iter()
+                .rev()
+                .find(|(_, block)| !matches!(block, RenderBlock::Pause(_)));
+            if !matches!(last_shown, Some((_, RenderBlock::Gap))) {
+                blocks.push((line_number, RenderBlock::Gap));
+            }
+            continue;
+        }
+
+        // Link reference definitions aren't shown
+        if link_definition(trimmed).is_some() {
+            previous_blank = after_blank;
+            continue;
+        }
+
+        // A ": definition" line right under a line of text, or under another definition,
+        // makes a definition list. Anything else with a leading colon is plain text
+        if let Some(definition) = trimmed.strip_prefix(": ") {
+            if continues_definition_list(&mut blocks, line_number) {
+                blocks.push((
+                    line_number,
+                    RenderBlock::Definition(definition.trim().to_string()),
+                ));
+                continue;
+            }
+        }
+
+        // Detect heading level and extract text without # or underline
+        let setext_level = line_iter
+            .peek()
+            .filter(|_| !trimmed.is_empty() && atx_heading(trimmed).is_none())
+            .and_then(|(_, next)| setext_underline_level(next));
+        let item = list_item(&expanded).filter(|_| !is_thematic_break(trimmed));
+        let block = if let Some((alt, path)) = image_line(trimmed) {
+            RenderBlock::Image {
+                alt: alt.to_string(),
+                path: path.to_string(),
+            }
+        } else if let Some((level, text)) = atx_heading(trimmed) {
+            RenderBlock::Heading {
+                level,
+                text: text.to_string(),
+            }
+        } else if let Some((indent, marker, text)) = item {
+            // An item is nested in the items above whose text it's indented at least as far
+            // as, so ordered and unordered lists nest in each other alike
+            while list_columns.last().is_some_and(|&column| indent < column) {
+                list_columns.pop();
+            }
+            let depth = list_columns.len();
+            list_columns.push(indent + marker.len() + 1);
+            RenderBlock::ListItem {
+                depth,
+                marker: marker.to_string(),
+                text: text.to_string(),
+            }
+        } else if let Some(level) = setext_level {
+            line_iter.next(); // consume the underline
+            RenderBlock::Heading {
+                level,
+                text: trimmed.to_string(),
+            }
+        } else if is_thematic_break(trimmed) {
+            RenderBlock::Rule
+        } else {
+            // A line of text continues the paragraph or list item above it, unless that ended
+            // in a hard break
+            if !newlines_as_breaks && !starts_own_line(trimmed) {
+                if let Some((
+                    _,
+                    RenderBlock::Paragraph(paragraph)
+                    | RenderBlock::ListItem {
+                        text: paragraph, ..
+                    },
+                )) = blocks.last_mut()
+                {
+                    if !paragraph.trim().is_empty() && !ends_with_hard_break(paragraph) {
+                        paragraph.push(' ');
+                        paragraph.push_str(trimmed);
+                        continue;
+                    }
+                }
+            }
+            RenderBlock::Paragraph(expanded)
+        };
+        // Anything else that isn't indented ends the lists
+        if !matches!(block, RenderBlock::ListItem { .. }) && !line.starts_with([' ', '\t']) {
+            list_columns.clear();
+        }
+        blocks.push((line_number, block));
+    }
+    blocks
+}
+
+// Whether a line leaves a `*`, `_`, backtick or `~~` marker unclosed, as a hint for typos.
+// Escaped markers, markers inside code, list bullets, markers standing alone between spaces
+// and underscores within words don't count
+fn has_unclosed_markup(text: &str, strikethrough: bool) -> bool {
+    let text = text.trim_start();
+    let text = text.strip_prefix("* ").unwrap_or(text);
+    let chars: Vec<char> = text.chars().collect();
+    let is_word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+    let is_space = |c: Option<&char>| c.is_none_or(|c| c.is_whitespace());
+
+    // Emphasis markers are counted by run, so "**" only pairs with another "**" and "***"
+    // counts as both
+    let mut singles = [0; 2]; // *, _
+    let mut doubles = [0; 2];
+    let (mut backticks, mut tildes) = (0, 0);
+    let mut in_code = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let run = chars[i..].iter().take_while(|&&c| c == ch).count();
+        let before = i.checked_sub(1).and_then(|j| chars.get(j));
+        let after = chars.get(i + run);
+        match ch {
+            '\\' => {
+                i += 2; // skip the escaped character
+                continue;
+            }
+            '`' => {
+                backticks += run;
+                if run % 2 == 1 {
+                    in_code = !in_code;
+                }
+            }
+            _ if in_code => {}
+            '*' | '_' if is_space(before) && is_space(after) => {}
+            '_' if is_word(before) && is_word(after) => {}
+            '*' | '_' => {
+                let kind = usize::from(ch == '_');
+                singles[kind] += run % 2;
+                doubles[kind] += run / 2;
+            }
+            '~' if strikethrough => tildes += run / 2,
+            _ => {}
+        }
+        i += run;
+    }
+    [
+        backticks, tildes, singles[0], singles[1], doubles[0], doubles[1],
+    ]
+    .iter()
+    .any(|count| count % 2 == 1)
+}
+
+// Take the inline markdown out of a line of text: emphasis and strikethrough markers, the
+// backticks around code and escaping backslashes, and link and image targets, keeping the
+// text they apply to. Markers that would be shown as written stay
+fn strip_inline_markup(
+    text: &str,
+    definitions: &HashMap<String, String>,
+    strikethrough: bool,
+) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let is_word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric());
+    let is_space = |c: Option<&char>| c.is_none_or(|c| c.is_whitespace());
+
+    let mut plain = String::with_capacity(text.len());
+    let mut in_code = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let run = chars[i..].iter().take_while(|&&c| c == ch).count();
+        let before = i.checked_sub(1).and_then(|j| chars.get(j));
+        let after = chars.get(i + run);
+        match ch {
+            '`' => {
+                if run % 2 == 1 {
+                    in_code = !in_code;
+                }
+            }
+            _ if in_code => plain.extend(&chars[i..i + run]),
+            '\\' => {
+                plain.extend(chars.get(i + 1));
+                i += 2;
+                continue;
+            }
+            '*' | '_' if is_space(before) && is_space(after) => plain.extend(&chars[i..i + run]),
+            '_' if is_word(before) && is_word(after) => plain.extend(&chars[i..i + run]),
+            '*' | '_' => {}
+            '~' if strikethrough => plain.extend(std::iter::repeat_n('~', run % 2)),
+            '[' => {
+                let rest: String = chars[i + 1..].iter().collect();
+                if let Some((link_text, _, used)) = parse_link(&rest, definitions) {
+                    // An image's "!" goes with its target
+                    if before == Some(&'!') {
+                        plain.pop();
+                    }
+                    plain.push_str(&strip_inline_markup(link_text, definitions, strikethrough));
+                    i += 1 + rest[..used].chars().count();
+                    continue;
+                }
+                plain.extend(&chars[i..i + run]);
+            }
+            _ => plain.extend(&chars[i..i + run]),
+        }
+        i += run;
+    }
+    plain
+}
+
+// Parse a link line "[label]: url", returning its label and URL. Footnotes ("[^1]: ...")
+// aren't links
+fn link_definition(trimmed: &str) -> Option<(&str, &str)> {
+    let rest = trimmed
+        .strip_prefix('[')
+        .filter(|rest| !rest.starts_with('^'))?;
+    let (label, url) = rest.split_once("]:")?;
+    let url = url.split_whitespace().next()?;
+    let url = url
+        .strip_prefix('<')
+        .and_then(|url| url.strip_suffix('>'))
+        .unwrap_or(url);
+    (!label.trim().is_empty() && !label.contains(['[', ']'])).then_some((label, url))
+}
+
+// Collect the link reference definitions in a document, outside code blocks. The first
+// definition of a label wins
+fn link_definitions(content: &str) -> HashMap<String, String> {
+    let mut definitions = HashMap::new();
+    let mut fence = None;
+    for line in content.lines() {
+        let trimmed = line.trim();
+        match fence {
+            Some(open) if is_closing_fence(trimmed, open) => fence = None,
+            Some(_) => {}
+            None if line.starts_with(CODE_INDENT) => {}
+            None => {
+                if let Some((open, _)) = code_fence(trimmed) {
+                    fence = Some(open);
+                } else if let Some((label, url)) = link_definition(trimmed) {
+                    definitions
+                        .entry(normalize_link_label(label))
+                        .or_insert_with(|| url.to_string());
+                }
+            }
+        }
+    }
+    definitions
+}
+
+// Link labels match regardless of case and spacing
+fn normalize_link_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+// Quote a CSV field if it has a comma, quote or line break in it
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// Parse a link following its opening "[": "text](url)", "text][ref]", "text][]" or "text]",
+// where references must be defined. Returns the link text, its URL and the bytes of `rest`
+// it used
+fn parse_link<'a>(
+    rest: &'a str,
+    definitions: &HashMap<String, String>,
+) -> Option<(&'a str, String, usize)> {
+    let close = rest.find(']')?;
+    let text = &rest[..close];
+    if text.trim().is_empty() || text.contains('[') {
+        return None;
+    }
+    let after = &rest[close + 1..];
+    let (url, used) = if let Some(target) = after.strip_prefix('(') {
+        let end = target.find(')')?;
+        (target[..end].trim().to_string(), close + 2 + end + 1)
+    } else if let Some(reference) = after.strip_prefix('[') {
+        let end = reference.find(']')?;
+        let label = if end == 0 { text } else { &reference[..end] };
+        let url = definitions.get(&normalize_link_label(label))?;
+        (url.clone(), close + 2 + end + 1)
+    } else {
+        let url = definitions.get(&normalize_link_label(text))?;
+        (url.clone(), close + 1)
+    };
+    Some((text, url, used))
+}
+
+// Annotate the line each pause cue stops at: the next row that's shown after it
+fn pause_cue_annotations(blocks: &[RenderBlock], block_lines: &[usize]) -> Vec<Annotation> {
+    blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| matches!(block, RenderBlock::Pause(_)))
+        .filter_map(|(index, _)| {
+            blocks[index..]
+                .iter()
+                .position(|block| !matches!(block, RenderBlock::Pause(_)))
+                .map(|offset| block_lines[index + offset])
+        })
+        .map(|line| Annotation {
+            kind: AnnotationKind::PauseCue,
+            lines: line..=line,
+        })
+        .collect()
+}
+
+// Remove pause cues written as `<!-- pause:3 -->` or `[[pause 3s]]` from a line, returning
+// the remaining text and the length of each pause in seconds
+fn extract_pause_cues(line: &str) -> (String, Vec<f32>) {
+    let mut text = String::new();
+    let mut pauses = Vec::new();
+    let mut rest = line;
+    while let Some(start) = [rest.find("<!--"), rest.find("[[")]
+        .into_iter()
+        .flatten()
+        .min()
+    {
+        let (open, close) = if rest[start..].starts_with("<!--") {
+            ("<!--", "-->")
+        } else {
+            ("[[", "]]")
+        };
+        let inner = &rest[start + open.len()..];
+        let Some(end) = inner.find(close) else {
+            break;
+        };
+        match pause_cue_seconds(&inner[..end]) {
+            Some(seconds) => {
+                text.push_str(&rest[..start]);
+                pauses.push(seconds);
+            }
+            None => text.push_str(&rest[..start + open.len() + end + close.len()]),
+        }
+        rest = &inner[end + close.len()..];
+    }
+    text.push_str(rest);
+    (text, pauses)
+}
+
+// Whether a definition on the given line can follow the last block: another definition just
+// above it, or a one-line paragraph just above it, which then becomes the term
+fn continues_definition_list(blocks: &mut [(usize, RenderBlock)], line_number: usize) -> bool {
+    let Some((line, block)) = blocks.last_mut() else {
+        return false;
+    };
+    if *line + 1 != line_number {
+        return false;
+    }
+    match block {
+        RenderBlock::Definition(_) => true,
+        RenderBlock::Paragraph(text) if !starts_own_line(text.trim()) => {
+            let term = text.trim().to_string();
+            *block = RenderBlock::Term(term);
+            true
+        }
+        _ => false,
+    }
+}
+
+// Byte index of the first `<!--` that isn't in `inline code`
+fn comment_start(text: &str) -> Option<usize> {
+    let mut in_code = false;
+    for (i, ch) in text.char_indices() {
+        if ch == '`' {
+            in_code = !in_code;
+        } else if !in_code && text[i..].starts_with("<!--") {
+            return Some(i);
+        }
+    }
+    None
+}
+
+// Whether a `<!--` in the text has no `-->` after it
+fn has_unclosed_comment(text: &str) -> bool {
+    let mut rest = text;
+    while let Some(start) = comment_start(rest) {
+        let inner = &rest[start + 4..];
+        match inner.find("-->") {
+            Some(end) => rest = &inner[end + 3..],
+            None => return true,
+        }
+    }
+    false
+}
+
+// Remove `<!-- comments -->` from text, returning what's left and the trimmed inside of each
+// non-empty comment. A `<!--` that is never closed, or is in inline code, is left as text
+fn extract_notes(text: &str) -> (String, Vec<String>) {
+    let mut remaining = String::new();
+    let mut notes = Vec::new();
+    let mut rest = text;
+    while let Some(start) = comment_start(rest) {
+        remaining.push_str(&rest[..start]);
+        let inner = &rest[start + 4..];
+        let Some(end) = inner.find("-->") else {
+            rest = &rest[start..];
+            break;
+        };
+        let (note, after) = (&inner[..end], &inner[end + 3..]);
+        if !note.trim().is_empty() {
+            notes.push(note.trim().to_string());
+        }
+        rest = after;
+    }
+    remaining.push_str(rest);
+    (remaining, notes)
+}
+
+// Length of the pause in the inside of a cue like "pause:3" or "pause 3s"
+fn pause_cue_seconds(cue: &str) -> Option<f32> {
+    let value = cue.trim().strip_prefix("pause")?;
+    let value = value.strip_prefix(':').unwrap_or(value).trim();
+    let value = value.strip_suffix('s').unwrap_or(value).trim();
+    value
+        .parse::<f32>()
+        .ok()
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+}
+
+// Lines that start a new row even when newlines don't: blank lines, list items, block quotes
+// and table rows
+fn starts_own_line(trimmed: &str) -> bool {
+    let ordered_item = trimmed.find(['.', ')']).is_some_and(|i| {
+        i > 0
+            && trimmed[..i].chars().all(|c| c.is_ascii_digit())
+            && trimmed[i + 1..].starts_with(' ')
+    });
+    trimmed.is_empty()
+        || ordered_item
+        || ["- ", "* ", "+ ", ">", "|"]
+            .into_iter()
+            .any(|prefix| trimmed.starts_with(prefix))
+}
+
+// Two trailing spaces or a backslash end a line with a hard break
+fn ends_with_hard_break(line: &str) -> bool {
+    line.ends_with("  ") || line.ends_with('\\')
+}
+
+// Number of columns a tab advances to when expanding leading whitespace
+const TAB_WIDTH: usize = 4;
+
+// Indentation that turns a line into an indented code block
+const CODE_INDENT: &str = "    ";
+
+// Expand tabs to spaces on TAB_WIDTH boundaries so indentation renders consistently
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for ch in line.chars() {
+        if ch == '\t' {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(ch);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+// Bullets for unordered list items, by depth, repeating for deeper lists
+const LIST_BULLETS: [&str; 3] = ["•", "▫", "▪"];
+
+// Indent of each list level, and the space between a marker and its text, in multiples of
+// the font size
+const LIST_INDENT: f32 = 1.5;
+const LIST_MARKER_GAP: f32 = 0.4;
+
+// Split a list item line into its indentation (after tabs are expanded), marker and text:
+// "-", "*" or "+", or up to nine digits and "." or ")", then a space
+fn list_item(expanded: &str) -> Option<(usize, &str, &str)> {
+    let item = expanded.trim_start_matches(' ');
+    let indent = expanded.len() - item.len();
+    let digits = item.chars().take_while(char::is_ascii_digit).count();
+    let marker_len = if item.starts_with(['-', '*', '+']) {
+        1
+    } else if (1..=9).contains(&digits) && item[digits..].starts_with(['.', ')']) {
+        digits + 1
+    } else {
+        return None;
+    };
+    let text = item[marker_len..].strip_prefix(' ')?;
+    Some((indent, &item[..marker_len], text.trim()))
+}
+
+// The alt text and path of a line, or a heading, that's nothing but an image: "![alt](path)",
+// optionally with a title after the path
+fn image_line(line: &str) -> Option<(&str, &str)> {
+    let line = atx_heading(line).map_or(line, |(_, text)| text);
+    let (alt, target) = line.strip_prefix("![")?.split_once("](")?;
+    let target = target.strip_suffix(')')?;
+    if alt.contains(']') || target.contains(')') {
+        return None;
+    }
+    let path = target.split_whitespace().next()?;
+    Some((alt.trim(), path))
+}
+
+// The text of a block that counts towards word counts
+fn block_text(block: &RenderBlock) -> Option<&str> {
+    match block {
+        RenderBlock::Heading { text, .. }
+        | RenderBlock::Paragraph(text)
+        | RenderBlock::Term(text)
+        | RenderBlock::Definition(text)
+        | RenderBlock::ListItem { text, .. }
+        | RenderBlock::Alert { text, .. }
+        | RenderBlock::IndentedCode(text) => Some(text),
+        RenderBlock::CodeBlock { code, .. } => Some(code),
+        _ => None,
+    }
+}
+
+// Count words for the status bar, ignoring markdown syntax like "#" and "-" on its own
+fn count_words(content: &str) -> usize {
+    content
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count()
+}
+
+// Replace emoji shortcodes like :rocket: with the emoji itself, leaving unknown shortcodes
+// and anything inside `inline code` as written
+fn expand_shortcodes(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut in_code = false;
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '`' {
+            in_code = !in_code;
+        } else if ch == ':' && !in_code {
+            if let Some(end) = rest[1..].find(':') {
+                if let Some(emoji) = emojis::get_by_shortcode(&rest[1..=end]) {
+                    expanded.push_str(emoji.as_str());
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+        expanded.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    expanded
+}
+
+// Replace inline HTML tags with their markdown equivalents (or a line break for <br>) when
+// `formatting` is set, dropping any other tags so they aren't shown literally. Spaces after a
+// <br> are dropped too, so the line it starts is flush, as it is when a joined paragraph's
+// source line ended in one
+fn convert_inline_html(text: &str, formatting: bool) -> String {
+    let mut converted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        converted.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        // Only treat `<name ...>` or `</name>` as a tag, so prose like "a < b" is kept
+        let tag_end = after
+            .find(['<', '>'])
+            .filter(|&end| after[end..].starts_with('>'));
+        let is_tag = after
+            .trim_start_matches('/')
+            .starts_with(|c: char| c.is_ascii_alphabetic());
+
+        match tag_end {
+            Some(end) if is_tag => {
+                let tag = after[..end].trim_start_matches('/').trim_end_matches('/');
+                let name = tag
+                    .split(|c: char| c.is_whitespace())
+                    .next()
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                match name.as_str() {
+                    "br" => converted.push('\n'),
+                    "b" | "strong" if formatting => converted.push_str("**"),
+                    "i" | "em" if formatting => converted.push('*'),
+                    "code" if formatting => converted.push('`'),
+                    _ => {}
+                }
+                rest = &after[end + 1..];
+                if name == "br" {
+                    rest = rest.trim_start_matches([' ', '\t']);
+                }
+            }
+            _ => {
+                converted.push('<');
+                rest = after;
+            }
+        }
+    }
+
+    converted.push_str(rest);
+    converted
+}
+
+// Detect an opening code fence (``` or ~~~), returning the fence and the language after it
+fn code_fence(trimmed: &str) -> Option<(&str, &str)> {
+    let marker = trimmed.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let fence_len = trimmed.chars().take_while(|&c| c == marker).count();
+    if fence_len < 3 {
+        return None;
+    }
+
+    let (fence, info) = trimmed.split_at(fence_len);
+    // Backticks in the info string mean this is inline code, not a fence
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    Some((fence, info.split_whitespace().next().unwrap_or_default()))
+}
+
+// A closing fence uses the same character at least as many times as the opening fence
+fn is_closing_fence(trimmed: &str, fence: &str) -> bool {
+    let marker = fence.chars().next().unwrap_or('`');
+    trimmed.len() >= fence.len() && trimmed.chars().all(|c| c == marker)
+}
+
+// Whether a color is dark enough to need light text, based on its relative luminance
+fn is_dark_color(color: [u8; 3]) -> bool {
+    let [r, g, b] = color.map(|c| c as f32 / 255.0);
+    0.2126 * r + 0.7152 * g + 0.0722 * b < 0.5
+}
+
+// The gray with the same relative luminance as a color, keeping its alpha
+fn grayscale(color: Color32) -> Color32 {
+    let [r, g, b, a] = color.to_array();
+    let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+    let gray = luminance.round().min(a as f32) as u8;
+    Color32::from_rgba_premultiplied(gray, gray, gray, a)
+}
+
+// Detect an ATX heading ("## Title"), returning its level and text. As in CommonMark, the
+// run of one to six #s must be followed by whitespace or end the line, so "#hashtag" stays
+// text, and a closing run of #s is dropped
+fn atx_heading(trimmed: &str) -> Option<(usize, &str)> {
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return None;
+    }
+    let text = rest.trim();
+
+    // The closing run needs whitespace before it, unless it's all there is
+    let unclosed = text.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with(char::is_whitespace) {
+        Some((level, unclosed.trim_end()))
+    } else {
+        Some((level, text))
+    }
+}
+
+// Put section numbers like "1", "1.1" and "1.2" before the headings up to the given level,
+// counting from the highest level used. A heading restarts the count of every level below it
+fn number_headings(blocks: &mut [RenderBlock], levels: usize) {
+    let numbered = |block: &RenderBlock| match block {
+        RenderBlock::Heading { level, .. } if *level <= levels => Some(*level),
+        _ => None,
+    };
+    let Some(top) = blocks.iter().filter_map(numbered).min() else {
+        return;
+    };
+    let mut counters = [0; 6];
+    for block in blocks {
+        let Some(level) = numbered(block) else {
+            continue;
+        };
+        counters[level - 1] += 1;
+        counters[level..].fill(0);
+        let number = counters[top - 1..level]
+            .iter()
+            .map(|count| count.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        if let RenderBlock::Heading { text, .. } = block {
+            *text = format!("{} {}", number, text);
+        }
+    }
+}
+
+// Detect a Setext underline ("===" for H1, "---" for H2) following a text line
+fn setext_underline_level(line: &str) -> Option<usize> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        None
+    } else if trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+// Detect a horizontal rule ("---", "***" or "___"), which needs a blank line above when
+// written with dashes so it isn't mistaken for a Setext underline
+fn is_thematic_break(trimmed: &str) -> bool {
+    let marker_count = |marker| trimmed.chars().filter(|&c| c == marker).count();
+    ['-', '*', '_']
+        .into_iter()
+        .any(|marker| marker_count(marker) >= 3 && trimmed.chars().all(|c| c == marker || c == ' '))
+}
+
+impl App for MarkPrompter {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.store_resume_position();
+        if let Some(geometry) = self.window_geometry {
+            self.settings.window = Some(geometry);
+            self.save_settings();
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.check_file_updates();
+        self.check_themes_updates();
+        self.apply_ui_scale(ctx);
+
+        self.track_window(ctx);
+
+        let focused = ctx.input(|i| i.focused);
+        self.handle_focus(focused);
+        self.handle_keyboard(ctx);
+        self.check_kiosk_start(ctx);
+        if ctx.input(|i| i.modifiers.alt) {
+            self.take_wheel_for_speed(ctx);
+        }
+
+        // Set background color from theme
+        let [bg_color, theme_text_color] = self.theme_colors(ctx);
+
+        let mut style = (*ctx.style()).clone();
+        style.visuals.panel_fill = bg_color;
+        style.visuals.window_fill = bg_color;
+        ctx.set_style(style);
+
+        let text_color_z = self.shown_color(Color32::from_rgb(
+            self.current_theme.heading_colors[0][0],
+            self.current_theme.heading_colors[0][1],
+            self.current_theme.heading_colors[0][2],
+        ));
+
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.style_mut()
+                .text_styles
+                .insert(egui::TextStyle::Body, egui::FontId::proportional(14.0));
+            self.render_status_bar(ui, theme_text_color.gamma_multiply(0.8));
+        });
+
+        // Once faded out, the controls panel gives its space to the content
+        let controls_opacity = self.controls_opacity(ctx);
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // Use columns with custom width ratio - give more space to controls panel
+            ui.with_layout(egui::Layout::left_to_right(egui::Align::TOP), |ui| {
+                if controls_opacity > 0.0 && self.settings.controls_collapsed {
+                    self.render_controls_toggle(ui, controls_opacity);
+                } else if controls_opacity > 0.0 {
+                    ui.allocate_ui_with_layout(
+                        egui::vec2(self.settings.controls_width, ui.available_height()),
+                        egui::Layout::top_down(egui::Align::LEFT),
+                        |ui| {
+                            ui.multiply_opacity(controls_opacity);
+
+                            // Left column - Controls panel
+                            ui.colored_label(
+                                text_color_z,
+                                RichText::new("MarkPrompter").size(32.0),
+                            );
+
+                            ui.add_space(10.0);
+
+                            // File controls
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_sized(
+                                        [80.0, 80.0],
+                                        egui::Button::new(
+                                            egui::RichText::new(format!("{} ", ICON_FOLDER_OPEN))
+                                                .size(28.0),
+                                        ),
+                                    )
+                                    .clicked()
+                                {
+                                    self.open_file();
+                                }
+
+                                // Manual reload for changes the watcher missed, keeping our place
+                                if ui
+                                    .add_enabled(
+                                        self.current_file.is_some(),
+                                        egui::Button::new(
+                                            egui::RichText::new(ICON_REFRESH).size(28.0),
+                                        )
+                                        .min_size(egui::vec2(80.0, 80.0)),
+                                    )
+                                    .on_hover_text("Reload from disk")
+                                    .clicked()
+                                {
+                                    self.preserve_scroll_fraction();
+                                    self.reload_file();
+                                }
+                            });
+
+                            // Step back through versions picked up by the file watcher
+                            if self.current_file.is_some() {
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .add_enabled(
+                                            !self.undo_reloads.is_empty(),
+                                            egui::Button::new(format!("{} Undo Reload", ICON_UNDO)),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.undo_reload();
+                                    }
+                                    if ui
+                                        .add_enabled(
+                                            !self.redo_reloads.is_empty(),
+                                            egui::Button::new(format!("{} Redo Reload", ICON_REDO)),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.redo_reload();
+                                    }
+                                });
+                            }
+
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(
+                                        !self.blocks.is_empty(),
+                                        egui::Button::new("Export Plain Text…"),
+                                    )
+                                    .on_hover_text(
+                                        "Save the script without its markdown, for other \
+                                         prompters",
+                                    )
+                                    .clicked()
+                                {
+                                    self.export_plain_text();
+                                }
+                                if ui
+                                    .checkbox(
+                                        &mut self.settings.export_heading_markup,
+                                        "Keep Heading #s",
+                                    )
+                                    .on_hover_text(
+                                        "Keep the # markers instead of uppercasing headings",
+                                    )
+                                    .changed()
+                                {
+                                    self.save_settings();
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(
+                                        !self.cue_log.is_empty(),
+                                        egui::Button::new(format!(
+                                            "Export Cue Log ({})…",
+                                            self.cue_log.len()
+                                        )),
+                                    )
+                                    .on_hover_text(
+                                        "Save when each heading passed while playing, as CSV",
+                                    )
+                                    .clicked()
+                                {
+                                    self.export_cue_log();
+                                }
+                                egui::ComboBox::from_id_salt("cue_log_time")
+                                    .selected_text(self.settings.cue_log_time.label())
+                                    .show_ui(ui, |ui| {
+                                        for option in CueTime::ALL {
+                                            if ui
+                                                .selectable_value(
+                                                    &mut self.settings.cue_log_time,
+                                                    option,
+                                                    option.label(),
+                                                )
+                                                .changed()
+                                            {
+                                                self.save_settings();
+                                            }
+                                        }
+                                    });
+                            });
+                            if let Some(status) = &self.export_status {
+                                match status {
+                                    Ok(message) => ui.label(message),
+                                    Err(message) => ui.colored_label(Color32::RED, message),
+                                };
+                            }
+
+                            self.render_playlist(ui);
+
+                            ui.add_space(20.0);
+                            ui.separator();
+                            ui.add_space(10.0);
+
+                            // Playback controls
+                            ui.heading("Playback");
+                            ui.add_space(5.0);
+
+                            ui.horizontal(|ui| {
+                                let play_pause_text = if self.is_playing {
+                                    egui::RichText::new(ICON_PAUSE).size(48.0)
+                                } else {
+                                    egui::RichText::new(ICON_PLAY_ARROW).size(48.0)
+                                };
+
+                                if ui
+                                    .add_sized([80.0, 80.0], egui::Button::new(play_pause_text))
+                                    .clicked()
+                                {
+                                    self.is_playing = !self.is_playing;
+                                    self.last_update = Instant::now();
+                                    if self.is_playing {
+                                        self.enter_practice_range();
+                                    }
+                                }
+
+                                if ui
+                                    .add_sized(
+                                        [80.0, 80.0],
+                                        egui::Button::new(
+                                            egui::RichText::new(ICON_SKIP_PREVIOUS).size(48.0),
+                                        ),
+                                    )
+                                    .clicked()
+                                {
+                                    self.restart();
+                                }
+                            });
+
+                            ui.add_space(10.0);
+
+                            // Speed controls
+                            ui.label("Scroll Speed").on_hover_text(
+                                "Scroll the mouse wheel over the speed to change it",
+                            );
+                            let speed_row = ui.horizontal(|ui| {
+                                if ui
+                                    .add_sized(
+                                        [60.0, 60.0],
+                                        egui::Button::new(
+                                            egui::RichText::new(ICON_REMOVE).size(36.0),
+                                        ),
+                                    )
+                                    .clicked()
+                                {
+                                    self.scroll_speed = self.settings.clamp_speed(
+                                        self.scroll_speed - self.settings.scroll_speed_step,
+                                    );
+                                }
+                                ui.add_space(10.0);
+                                ui.add(
+                                    egui::DragValue::new(&mut self.scroll_speed)
+                                        .range(
+                                            self.settings.min_scroll_speed
+                                                ..=self.settings.max_scroll_speed,
+                                        )
+                                        .speed(self.settings.speed_unit.drag_speed())
+                                        .suffix(self.settings.speed_unit.suffix()),
+                                );
+                                ui.add_space(10.0);
+                                if ui
+                                    .add_sized(
+                                        [60.0, 60.0],
+                                        egui::Button::new(egui::RichText::new(ICON_ADD).size(36.0)),
+                                    )
+                                    .clicked()
+                                {
+                                    self.scroll_speed = self.settings.clamp_speed(
+                                        self.scroll_speed + self.settings.scroll_speed_step,
+                                    );
+                                }
+                            });
+                            if ui.rect_contains_pointer(speed_row.response.rect) {
+                                self.take_wheel_for_speed(ctx);
+                            }
+
+                            // Speed presets
+                            ui.horizontal(|ui| {
+                                for (index, name) in SPEED_PRESET_NAMES.into_iter().enumerate() {
+                                    let preset = self.settings.speed_presets[index];
+                                    if ui
+                                        .selectable_label(self.scroll_speed == preset, name)
+                                        .on_hover_text(self.settings.speed_unit.format(preset))
+                                        .clicked()
+                                    {
+                                        self.apply_speed_preset(index);
+                                    }
+                                }
+                            });
+
+                            self.render_calibration(ui);
+                            self.render_target_duration(ui);
+
+                            ui.add_space(10.0);
+
+                            // Position through the script, which can be typed or dragged to jump
+                            ui.horizontal(|ui| {
+                                ui.label("Position:");
+                                let mut percent = self.progress_percent();
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut percent)
+                                            .range(0.0..=100.0)
+                                            .speed(0.5)
+                                            .max_decimals(0)
+                                            .suffix("%"),
+                                    )
+                                    .changed()
+                                {
+                                    self.jump_to_percent(percent);
+                                }
+                            });
+
+                            ui.add_space(10.0);
+
+                            // Marker controls
+                            ui.label("Markers");
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut self.selected_marker)
+                                        .range(1..=MARKER_COUNT)
+                                        .prefix("#"),
+                                );
+                                let slot = self.selected_marker - 1;
+                                if ui.button("Set marker").clicked() {
+                                    self.set_marker(slot);
+                                }
+                                if ui
+                                    .add_enabled(
+                                        self.markers[slot].is_some(),
+                                        egui::Button::new("Go to marker"),
+                                    )
+                                    .clicked()
+                                {
+                                    self.go_to_marker(slot);
+                                }
+                            });
+
+                            let set_markers: Vec<String> = self
+                                .markers
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, position)| position.is_some())
+                                .map(|(slot, _)| (slot + 1).to_string())
+                                .collect();
+                            if !set_markers.is_empty() {
+                                ui.label(format!("Set: {}", set_markers.join(", ")));
+                            }
+
+                            // Rehearse one segment by playing only between two markers
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .checkbox(&mut self.practice_range, "Practice Range")
+                                    .on_hover_text(
+                                        "Play only from one marker to another, which is treated \
+                                         as the end of the script",
+                                    )
+                                    .changed()
+                                    && self.practice_range
+                                {
+                                    self.enter_practice_range();
+                                }
+                                let (start, end) = &mut self.practice_markers;
+                                ui.add(
+                                    egui::DragValue::new(start)
+                                        .range(1..=MARKER_COUNT)
+                                        .prefix("#"),
+                                );
+                                ui.label("to");
+                                ui.add(
+                                    egui::DragValue::new(end)
+                                        .range(1..=MARKER_COUNT)
+                                        .prefix("#"),
+                                );
+                            });
+                            if self.practice_range && self.practice_bounds().is_none() {
+                                ui.label(format!(
+                                    "Set markers {} and {}, in order, to practice between them",
+                                    self.practice_markers.0, self.practice_markers.1
+                                ));
+                            }
+
+                            ui.add_space(10.0);
+                            self.render_outline(ui);
+
+                            ui.add_space(20.0);
+                            ui.separator();
+                            ui.add_space(10.0);
+
+                            // Feature toggles
+                            ui.heading("Settings");
+                            ui.add_space(5.0);
+
+                            ui.checkbox(&mut self.pause_at_headings, "Pause at Headings");
+
+                            if self.pause_at_headings {
+                                pause_duration_row(ui, &mut self.heading_pause_duration);
+                            }
+
+                            ui.checkbox(&mut self.pause_at_paragraphs, "Pause at Paragraphs")
+                                .on_hover_text("Pause briefly after each blank line");
+
+                            if self.pause_at_paragraphs {
+                                pause_duration_row(ui, &mut self.paragraph_pause_duration);
+                            }
+
+                            let mut end_changed = false;
+                            egui::ComboBox::from_label("At End")
+                                .selected_text(self.settings.end_behavior.label())
+                                .show_ui(ui, |ui| {
+                                    for option in EndBehavior::ALL {
+                                        end_changed |= ui
+                                            .selectable_value(
+                                                &mut self.settings.end_behavior,
+                                                option,
+                                                option.label(),
+                                            )
+                                            .changed();
+                                    }
+                                });
+                            if self.end_behavior() == EndBehavior::Loop {
+                                ui.horizontal(|ui| {
+                                    ui.label("Loop Delay:");
+                                    end_changed |= ui
+                                        .add(
+                                            egui::DragValue::new(&mut self.settings.loop_delay)
+                                                .range(0.0..=MAX_LOOP_DELAY)
+                                                .speed(0.1)
+                                                .max_decimals(1)
+                                                .suffix("s"),
+                                        )
+                                        .changed();
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("Lead-in:");
+                                    end_changed |= ui
+                                        .add(
+                                            egui::DragValue::new(&mut self.settings.loop_lead_in)
+                                                .range(0.0..=MAX_LOOP_LEAD_IN)
+                                                .speed(0.1)
+                                                .max_decimals(1)
+                                                .suffix(" lines"),
+                                        )
+                                        .on_hover_text(
+                                            "Start this many lines before the top when looping",
+                                        )
+                                        .changed();
+                                });
+                            }
+                            if end_changed {
+                                self.reversing = false;
+                                self.loop_wait = None;
+                                self.save_settings();
+                            }
+
+                            // Separate from playing by hand: nobody needs to be there to start it
+                            if ui
+                                .checkbox(&mut self.settings.kiosk_mode, "Kiosk Mode")
+                                .on_hover_text(
+                                    "Start playing on its own after a file loads, and loop at \
+                                     the end, for unattended displays",
+                                )
+                                .changed()
+                            {
+                                self.reversing = false;
+                                self.kiosk_start = None;
+                                self.save_settings();
+                            }
+                            if self.settings.kiosk_mode {
+                                ui.horizontal(|ui| {
+                                    ui.label("Start After:");
+                                    if ui
+                                        .add(
+                                            egui::DragValue::new(
+                                                &mut self.settings.kiosk_start_delay,
+                                            )
+                                            .range(0.0..=MAX_KIOSK_START_DELAY)
+                                            .speed(0.1)
+                                            .max_decimals(1)
+                                            .suffix("s"),
+                                        )
+                                        .changed()
+                                    {
+                                        self.save_settings();
+                                    }
+                                });
+                            }
+
+                            let mut link_changed = false;
+                            egui::ComboBox::from_label("Link Click")
+                                .selected_text(self.settings.link_action.label())
+                                .show_ui(ui, |ui| {
+                                    for option in LinkAction::ALL {
+                                        link_changed |= ui
+                                            .selectable_value(
+                                                &mut self.settings.link_action,
+                                                option,
+                                                option.label(),
+                                            )
+                                            .changed();
+                                    }
+                                });
+                            if self.settings.link_action != LinkAction::Ignore {
+                                link_changed |= ui
+                                    .checkbox(
+                                        &mut self.settings.links_inert_while_playing,
+                                        "Links Inert While Playing",
+                                    )
+                                    .changed();
+                            }
+                            if link_changed {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.pause_on_focus_loss,
+                                    "Pause on Focus Loss",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if self.settings.pause_on_focus_loss
+                                && ui
+                                    .checkbox(&mut self.settings.resume_on_focus, "Resume on Focus")
+                                    .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            // Speed unit
+                            ui.add_space(5.0);
+                            let mut speed_unit = self.settings.speed_unit;
+                            egui::ComboBox::from_label("Speed Unit")
+                                .selected_text(speed_unit.label())
+                                .show_ui(ui, |ui| {
+                                    for option in SpeedUnit::ALL {
+                                        ui.selectable_value(
+                                            &mut speed_unit,
+                                            option,
+                                            option.label(),
+                                        );
+                                    }
+                                });
+                            self.set_speed_unit(speed_unit);
+
+                            // Speed bounds
+                            ui.add_space(5.0);
+                            ui.label("Speed Limits");
+                            let unit = self.settings.speed_unit;
+                            let (lowest, highest) = unit.limit_range();
+                            let mut speed_limits_changed = false;
+                            ui.horizontal(|ui| {
+                                ui.label("Min:");
+                                speed_limits_changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.min_scroll_speed)
+                                            .range(lowest..=self.settings.max_scroll_speed - lowest)
+                                            .speed(unit.drag_speed())
+                                            .suffix(unit.suffix()),
+                                    )
+                                    .changed();
+                                ui.label("Max:");
+                                speed_limits_changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.max_scroll_speed)
+                                            .range(
+                                                self.settings.min_scroll_speed + lowest..=highest,
+                                            )
+                                            .speed(unit.drag_speed())
+                                            .suffix(unit.suffix()),
+                                    )
+                                    .changed();
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Step:");
+                                speed_limits_changed |= ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.scroll_speed_step)
+                                            .range(lowest / 10.0..=highest / 50.0)
+                                            .speed(unit.drag_speed() / 10.0)
+                                            .suffix(unit.suffix()),
+                                    )
+                                    .changed();
+                            });
+                            ui.label("Speed Presets");
+                            ui.horizontal(|ui| {
+                                for (index, name) in SPEED_PRESET_NAMES.into_iter().enumerate() {
+                                    ui.label(format!("{}:", name));
+                                    speed_limits_changed |= ui
+                                        .add(
+                                            egui::DragValue::new(
+                                                &mut self.settings.speed_presets[index],
+                                            )
+                                            .range(
+                                                self.settings.min_scroll_speed
+                                                    ..=self.settings.max_scroll_speed,
+                                            )
+                                            .speed(unit.drag_speed()),
+                                        )
+                                        .changed();
+                                }
+                            });
+                            if speed_limits_changed {
+                                self.settings.validate();
+                                self.scroll_speed = self.settings.clamp_speed(self.scroll_speed);
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.center_current_line,
+                                    "Center Current Line",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.two_columns, "Two Columns")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.per_file_settings, "Per-File Settings")
+                                .changed()
+                            {
+                                self.store_markers();
+                                self.save_settings();
+                            }
+
+                            if self.settings.per_file_settings {
+                                ui.horizontal(|ui| {
+                                    if ui.button("Use as Default").clicked() {
+                                        self.use_as_default();
+                                    }
+                                    if ui
+                                        .add_enabled(
+                                            self.current_file_key().is_some_and(|key| {
+                                                self.settings.files.contains_key(&key)
+                                            }),
+                                            egui::Button::new("Clear File Settings"),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.clear_file_settings();
+                                    }
+                                });
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.persist_markers, "Remember Markers")
+                                .changed()
+                            {
+                                self.store_markers();
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.resume_files,
+                                    "Resume Where I Left Off",
+                                )
+                                .on_hover_text("Reopen each file at the place it was left")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            ui.checkbox(&mut self.show_raw, "Show Markdown Source");
+
+                            if ui
+                                .checkbox(&mut self.settings.interpret_html, "Interpret HTML Tags")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.show_notes, "Show Notes")
+                                .on_hover_text(
+                                    "Show <!-- comments --> dimmed instead of hiding them",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                                self.parse_markdown();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.emoji_shortcodes, "Emoji Shortcodes")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.newlines_as_breaks,
+                                    "Treat Newlines as Line Breaks",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                                self.parse_markdown();
+                            }
+
+                            ui.horizontal(|ui| {
+                                ui.label("Paragraph Gap:");
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.paragraph_gap)
+                                            .range(0.0..=MAX_PARAGRAPH_GAP)
+                                            .speed(0.05)
+                                            .max_decimals(2)
+                                            .suffix("×"),
+                                    )
+                                    .on_hover_text(
+                                        "Space left by blank lines, in multiples of the font size",
+                                    )
+                                    .changed()
+                                {
+                                    self.preserve_scroll_fraction();
+                                    self.save_settings();
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                ui.label("Banner Height:");
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.banner_max_height)
+                                            .range(MIN_BANNER_HEIGHT..=MAX_BANNER_HEIGHT)
+                                            .speed(1.0)
+                                            .suffix("pt"),
+                                    )
+                                    .on_hover_text("Tallest a line that's only an image is shown")
+                                    .changed()
+                                {
+                                    self.preserve_scroll_fraction();
+                                    self.save_settings();
+                                }
+                            });
+
+                            ui.collapsing("Content Padding", |ui| {
+                                let mut changed = false;
+                                for (label, side) in self.settings.content_padding.sides_mut() {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("{}:", label));
+                                        changed |= ui
+                                            .add(
+                                                egui::DragValue::new(side)
+                                                    .range(0.0..=MAX_CONTENT_PADDING)
+                                                    .speed(1.0)
+                                                    .suffix("px"),
+                                            )
+                                            .changed();
+                                    });
+                                }
+                                if changed {
+                                    self.save_settings();
+                                }
+                            });
+
+                            ui.collapsing("Markdown Extensions", |ui| {
+                                let extensions = &mut self.settings.markdown_extensions;
+                                let mut changed = false;
+                                changed |= ui
+                                    .checkbox(&mut extensions.strikethrough, "Strikethrough (~~)")
+                                    .changed();
+                                changed |= ui.checkbox(&mut extensions.table, "Tables").changed();
+                                changed |= ui
+                                    .checkbox(&mut extensions.tasklist, "Task Lists ([ ] / [x])")
+                                    .changed();
+                                changed |= ui
+                                    .checkbox(&mut extensions.footnotes, "Footnotes")
+                                    .changed();
+                                if changed {
+                                    self.save_settings();
+                                    self.parse_markdown();
+                                }
+                            });
+
+                            if ui
+                                .checkbox(&mut self.settings.show_annotations, "Show Annotations")
+                                .on_hover_text("Tint the lines with markers and pause cues")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
 
-                        ui.add_space(5.0);
+                            ui.horizontal(|ui| {
+                                let mut changed = ui
+                                    .checkbox(&mut self.settings.spotlight, "Spotlight")
+                                    .on_hover_text(
+                                        "Dim text further from the focus line, so the current \
+                                     line stands out",
+                                    )
+                                    .changed();
+                                if self.settings.spotlight {
+                                    changed |= ui
+                                        .add(
+                                            egui::Slider::new(
+                                                &mut self.settings.spotlight_strength,
+                                                0.0..=1.0,
+                                            )
+                                            .text("Strength"),
+                                        )
+                                        .changed();
+                                }
+                                if changed {
+                                    self.save_settings();
+                                }
+                            });
 
-                        // Font size
-                        ui.horizontal(|ui| {
                             if ui
-                                .add_sized(
-                                    [50.0, 50.0],
-                                    egui::Button::new(
-                                        egui::RichText::new(ICON_TEXT_DECREASE).size(32.0),
-                                    ),
+                                .checkbox(
+                                    &mut self.settings.highlight_sentence,
+                                    "Highlight Sentence",
+                                )
+                                .on_hover_text(
+                                    "Tint the sentence at the focus line in the theme's accent \
+                                     color",
                                 )
-                                .clicked()
+                                .changed()
                             {
-                                self.font_size = (self.font_size - 1.0).max(8.0);
+                                self.save_settings();
                             }
+
+                            if ui
+                                .checkbox(&mut self.settings.lint_markup, "Flag Unclosed Markup")
+                                .on_hover_text(
+                                    "Mark lines with an unclosed *, _, ` or ~~, to catch typos",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.show_line_numbers, "Show Line Numbers")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            ui.horizontal(|ui| {
+                                ui.label("Code Font:");
+                                let name = self
+                                    .settings
+                                    .code_font
+                                    .as_ref()
+                                    .and_then(|path| path.file_name())
+                                    .map_or("Default".into(), |name| name.to_string_lossy());
+                                ui.label(name);
+                            });
+                            ui.horizontal(|ui| {
+                                if ui.button("Choose…").clicked() {
+                                    if let Some(path) = FileDialog::new()
+                                        .add_filter("Font", &["ttf", "otf"])
+                                        .pick_file()
+                                    {
+                                        self.set_code_font(Some(path));
+                                    }
+                                }
+                                if ui
+                                    .add_enabled(
+                                        self.settings.code_font.is_some(),
+                                        egui::Button::new("Default"),
+                                    )
+                                    .clicked()
+                                {
+                                    self.set_code_font(None);
+                                }
+                                let response = ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.code_font_scale)
+                                            .range(MIN_CODE_FONT_SCALE..=MAX_CODE_FONT_SCALE)
+                                            .speed(0.01)
+                                            .max_decimals(2)
+                                            .suffix("×"),
+                                    )
+                                    .on_hover_text("Code size relative to the surrounding text");
+                                if response.changed() {
+                                    self.preserve_scroll_fraction();
+                                    self.save_settings();
+                                }
+                            });
+                            if let Some(error) = &self.code_font_error {
+                                ui.colored_label(Color32::RED, error);
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.end_of_script_sound,
+                                    "Sound at End of Script",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.metronome, "Metronome")
+                                .on_hover_text("Click at a steady tempo while playing")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+                            if self.settings.metronome {
+                                ui.horizontal(|ui| {
+                                    ui.label("Tempo:");
+                                    let follows_pace = self.settings.metronome_follows_pace;
+                                    let mut bpm = self.metronome_bpm();
+                                    let response = ui.add_enabled(
+                                        !follows_pace,
+                                        egui::DragValue::new(&mut bpm)
+                                            .range(MIN_METRONOME_BPM..=MAX_METRONOME_BPM)
+                                            .speed(1.0)
+                                            .max_decimals(0)
+                                            .suffix(" bpm"),
+                                    );
+                                    if response.changed() {
+                                        self.settings.metronome_bpm = bpm;
+                                        self.save_settings();
+                                    }
+                                });
+                                if ui
+                                    .checkbox(
+                                        &mut self.settings.metronome_follows_pace,
+                                        "Follow Reading Pace",
+                                    )
+                                    .on_hover_text("One click per word at the current speed")
+                                    .changed()
+                                {
+                                    self.save_settings();
+                                }
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.state_events, "Print State Events")
+                                .on_hover_text(
+                                    "Print a line of JSON to stdout when playback starts, stops \
+                                     or ends, or a new section is reached",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.fade_in, "Fade In on Load")
+                                .on_hover_text(
+                                    "Fade the content in when a file is opened or reloaded",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.animate_theme_changes,
+                                    "Animate Theme Changes",
+                                )
+                                .on_hover_text(
+                                    "Blend into a new theme's colors instead of snapping",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.subpixel_scrolling,
+                                    "Subpixel Scrolling",
+                                )
+                                .on_hover_text(
+                                    "Move text by fractions of a pixel while playing, so slow \
+                                     speeds don't judder. Turn off for text snapped to the pixel \
+                                     grid",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.hide_cursor,
+                                    "Hide Cursor While Playing",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            ui.horizontal(|ui| {
+                                ui.label("Panel Width:");
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.controls_width)
+                                            .range(MIN_CONTROLS_WIDTH..=MAX_CONTROLS_WIDTH)
+                                            .speed(1.0)
+                                            .suffix("px"),
+                                    )
+                                    .changed()
+                                {
+                                    self.preserve_scroll_fraction();
+                                    self.save_settings();
+                                }
+                            });
+
+                            if ui
+                                .checkbox(
+                                    &mut self.settings.auto_hide_controls,
+                                    "Hide Controls While Playing",
+                                )
+                                .on_hover_text(
+                                    "Fade this panel out while playing. Move the pointer to the \
+                                     left edge to bring it back",
+                                )
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+                            if self.settings.auto_hide_controls {
+                                let mut changed = false;
+                                ui.horizontal(|ui| {
+                                    ui.label("After:");
+                                    changed |= ui
+                                        .add(
+                                            egui::DragValue::new(
+                                                &mut self.settings.controls_hide_delay,
+                                            )
+                                            .range(0.0..=MAX_CONTROLS_HIDE_DELAY)
+                                            .speed(0.1)
+                                            .max_decimals(1)
+                                            .suffix("s"),
+                                        )
+                                        .changed();
+                                    ui.label("Edge:");
+                                    changed |= ui
+                                        .add(
+                                            egui::DragValue::new(
+                                                &mut self.settings.controls_reveal_width,
+                                            )
+                                            .range(0.0..=MAX_CONTROLS_WIDTH)
+                                            .speed(1.0)
+                                            .suffix("px"),
+                                        )
+                                        .on_hover_text("Moving the pointer this close shows it")
+                                        .changed();
+                                });
+                                if changed {
+                                    self.save_settings();
+                                }
+                            }
+
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .checkbox(
+                                        &mut self.settings.show_safe_areas,
+                                        "Safe Area Guides",
+                                    )
+                                    .on_hover_text(
+                                        "Outline title and action safe areas for framing on camera",
+                                    )
+                                    .changed()
+                                {
+                                    self.save_settings();
+                                }
+                                let mut changed = false;
+                                for percent in &mut self.settings.safe_areas {
+                                    changed |= ui
+                                        .add(
+                                            egui::DragValue::new(percent)
+                                                .range(MIN_SAFE_AREA..=100.0)
+                                                .suffix("%"),
+                                        )
+                                        .changed();
+                                }
+                                if changed {
+                                    self.save_settings();
+                                }
+                            });
+
+                            if ui
+                                .checkbox(&mut self.settings.show_clock, "Show Clock")
+                                .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            if self.settings.show_clock
+                                && ui
+                                    .checkbox(&mut self.settings.clock_24_hour, "24-Hour Clock")
+                                    .changed()
+                            {
+                                self.save_settings();
+                            }
+
+                            // Text alignment
+                            let mut alignment_changed = false;
+                            for (label, alignment) in [
+                                ("Text Alignment", &mut self.settings.text_alignment),
+                                ("Heading Alignment", &mut self.settings.heading_alignment),
+                            ] {
+                                egui::ComboBox::from_label(label)
+                                    .selected_text(alignment.label())
+                                    .show_ui(ui, |ui| {
+                                        for option in TextAlignment::ALL {
+                                            alignment_changed |= ui
+                                                .selectable_value(alignment, option, option.label())
+                                                .changed();
+                                        }
+                                    });
+                            }
+                            if alignment_changed {
+                                self.save_settings();
+                            }
+
+                            if ui
+                                .checkbox(&mut self.settings.number_headings, "Number Sections")
+                                .on_hover_text("Show section numbers like 1.2 before headings")
+                                .changed()
+                            {
+                                self.save_settings();
+                                self.parse_markdown();
+                            }
+
+                            ui.horizontal(|ui| {
+                                let mut indent_changed = ui
+                                    .checkbox(&mut self.settings.indent_headings, "Indent Sections")
+                                    .on_hover_text(
+                                        "Indent each heading and the text under it by its level",
+                                    )
+                                    .changed();
+                                if self.settings.indent_headings {
+                                    indent_changed |= ui
+                                        .add(
+                                            egui::DragValue::new(&mut self.settings.heading_indent)
+                                                .range(0.0..=MAX_HEADING_INDENT)
+                                                .speed(1.0)
+                                                .max_decimals(0)
+                                                .suffix("px per level"),
+                                        )
+                                        .changed();
+                                }
+                                if indent_changed {
+                                    self.preserve_scroll_fraction();
+                                    self.save_settings();
+                                }
+                            });
+
+                            // Applied once the value is let go, so the field doesn't move away
+                            // from the pointer while it's being dragged
+                            ui.horizontal(|ui| {
+                                ui.label("UI Scale:");
+                                let mut scale = self
+                                    .ui_scale_edit
+                                    .or(self.settings.ui_scale)
+                                    .unwrap_or_else(|| ui.ctx().pixels_per_point());
+                                let response = ui.add(
+                                    egui::DragValue::new(&mut scale)
+                                        .range(MIN_UI_SCALE..=MAX_UI_SCALE)
+                                        .speed(0.01)
+                                        .max_decimals(2)
+                                        .suffix("×"),
+                                );
+                                if response.dragged() {
+                                    self.ui_scale_edit = Some(scale);
+                                } else if response.changed() || response.drag_stopped() {
+                                    self.ui_scale_edit = None;
+                                    self.set_ui_scale(scale);
+                                }
+                                if ui
+                                    .button("Reset")
+                                    .on_hover_text("Use the display's own scale")
+                                    .clicked()
+                                {
+                                    if let Some(native) = ui.ctx().native_pixels_per_point() {
+                                        self.set_ui_scale(native);
+                                    }
+                                }
+                            });
+
+                            let mut scrollbar_changed = false;
+                            egui::ComboBox::from_label("Scrollbar")
+                                .selected_text(self.settings.scrollbar.label())
+                                .show_ui(ui, |ui| {
+                                    for option in ScrollbarMode::ALL {
+                                        scrollbar_changed |= ui
+                                            .selectable_value(
+                                                &mut self.settings.scrollbar,
+                                                option,
+                                                option.label(),
+                                            )
+                                            .changed();
+                                    }
+                                });
+                            scrollbar_changed |= ui
+                                .checkbox(
+                                    &mut self.settings.themed_scrollbar,
+                                    "Theme Colored Scrollbar",
+                                )
+                                .changed();
+                            if scrollbar_changed {
+                                self.save_settings();
+                            }
+
+                            ui.add_space(5.0);
+
+                            // Font size
+                            if !ui.input(|i| i.pointer.any_down()) {
+                                self.font_step_repeat = None;
+                            }
+                            ui.horizontal(|ui| {
+                                self.font_step_button(ui, ICON_TEXT_DECREASE, -1.0);
+                                ui.add_space(10.0);
+                                let mut font_size = self.font_size;
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut font_size)
+                                            .range(MIN_FONT_SIZE..=MAX_FONT_SIZE)
+                                            .speed(0.5)
+                                            .max_decimals(0)
+                                            .suffix("px"),
+                                    )
+                                    .changed()
+                                {
+                                    self.choose_font_size(font_size);
+                                }
+                                ui.add_space(10.0);
+                                self.font_step_button(ui, ICON_TEXT_INCREASE, 1.0);
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Step:");
+                                if ui
+                                    .add(
+                                        egui::DragValue::new(&mut self.settings.font_size_step)
+                                            .range(0.5..=MAX_FONT_SIZE_STEP)
+                                            .speed(0.1)
+                                            .max_decimals(1)
+                                            .suffix("px"),
+                                    )
+                                    .changed()
+                                {
+                                    self.save_settings();
+                                }
+                                if ui
+                                    .button("Reset")
+                                    .on_hover_text(
+                                        "Go back to the theme's or the default font size",
+                                    )
+                                    .clicked()
+                                {
+                                    self.reset_font_size();
+                                }
+                            });
+
+                            ui.add_space(20.0);
+                            ui.separator();
                             ui.add_space(10.0);
-                            ui.label(
-                                egui::RichText::new(format!("{:.0}px", self.font_size)).size(20.0),
-                            );
-                            ui.add_space(10.0);
+
+                            // Theme selection
+                            ui.heading("Theme");
+                            ui.add_space(5.0);
+
+                            let mut theme_picked = false;
+                            egui::ComboBox::from_label("")
+                                .selected_text(self.current_theme.name.clone())
+                                .show_ui(ui, |ui| {
+                                    for theme in &self.available_themes {
+                                        let swatch_id = ui.id().with(("swatch", &theme.name));
+                                        let button = egui::Button::selectable(
+                                            self.current_theme.name == theme.name,
+                                            (
+                                                egui::Atom::custom(swatch_id, THEME_SWATCH_SIZE),
+                                                theme.name.clone(),
+                                            ),
+                                        )
+                                        .atom_ui(ui);
+                                        if let Some(rect) = button.rect(swatch_id) {
+                                            paint_theme_swatch(ui.painter(), rect, theme);
+                                        }
+                                        if button.response.clicked() {
+                                            self.current_theme = theme.clone();
+                                            self.theme_override = None;
+                                            theme_picked = true;
+
+                                            // With per-file settings the theme is remembered for
+                                            // the open file instead of becoming the default
+                                            if !self.settings.per_file_settings
+                                                || self.current_file.is_none()
+                                            {
+                                                self.default_theme = theme.name.clone();
+                                                // Save theme preference
+                                                if let Err(e) = save_theme_preference(&theme.name) {
+                                                    eprintln!(
+                                                        "Failed to save theme preference: {}",
+                                                        e
+                                                    );
+                                                }
+                                            }
+                                        }
+                                    }
+                                });
+
+                            if theme_picked {
+                                self.apply_theme_font_size();
+                            }
+                            if let Some(error) = &self.themes_error {
+                                ui.colored_label(Color32::RED, error);
+                            }
+
                             if ui
-                                .add_sized(
-                                    [50.0, 50.0],
-                                    egui::Button::new(
-                                        egui::RichText::new(ICON_TEXT_INCREASE).size(32.0),
-                                    ),
+                                .checkbox(&mut self.settings.grayscale, "Grayscale")
+                                .on_hover_text(
+                                    "Show the theme in grays, keeping each color's brightness",
                                 )
-                                .clicked()
+                                .changed()
                             {
-                                self.font_size = (self.font_size + 1.0).min(72.0);
+                                self.save_settings();
                             }
-                        });
 
-                        ui.add_space(20.0);
-                        ui.separator();
-                        ui.add_space(10.0);
+                            let mut decoration_changed = false;
+                            egui::ComboBox::from_label("Heading Style")
+                                .selected_text(self.current_theme.heading_decoration.label())
+                                .show_ui(ui, |ui| {
+                                    for option in HeadingDecoration::ALL {
+                                        decoration_changed |= ui
+                                            .selectable_value(
+                                                &mut self.current_theme.heading_decoration,
+                                                option,
+                                                option.label(),
+                                            )
+                                            .changed();
+                                    }
+                                });
+                            if decoration_changed {
+                                self.update_current_theme();
+                            }
+
+                            // Inline code colors, previewed live and saved once the picker is let go
+                            let theme_text_color = Color32::from_rgb(
+                                self.current_theme.text_color[0],
+                                self.current_theme.text_color[1],
+                                self.current_theme.text_color[2],
+                            );
+                            ui.horizontal(|ui| {
+                                ui.label("Inline Code:");
+                                let mut background = self.current_theme.inline_code_background();
+                                if ui
+                                    .color_edit_button_srgba(&mut background)
+                                    .on_hover_text("Background")
+                                    .changed()
+                                {
+                                    self.current_theme.inline_code_background =
+                                        Some(background.to_srgba_unmultiplied());
+                                    self.theme_edited = true;
+                                }
+                                let mut color =
+                                    self.current_theme.inline_code_color(theme_text_color);
+                                if ui
+                                    .color_edit_button_srgba(&mut color)
+                                    .on_hover_text("Text")
+                                    .changed()
+                                {
+                                    self.current_theme.inline_code_color =
+                                        Some([color.r(), color.g(), color.b()]);
+                                    self.theme_edited = true;
+                                }
+                                if (self.current_theme.inline_code_background.is_some()
+                                    || self.current_theme.inline_code_color.is_some())
+                                    && ui.button("Reset").clicked()
+                                {
+                                    self.current_theme.inline_code_background = None;
+                                    self.current_theme.inline_code_color = None;
+                                    self.update_current_theme();
+                                }
+                            });
+
+                            ui.horizontal(|ui| {
+                                ui.label("Accent:");
+                                let mut accent = self.current_theme.accent_color();
+                                if ui
+                                    .color_edit_button_srgba(&mut accent)
+                                    .on_hover_text("Links and markers")
+                                    .changed()
+                                {
+                                    self.current_theme.accent_color =
+                                        Some([accent.r(), accent.g(), accent.b()]);
+                                    self.theme_edited = true;
+                                }
+                                if self.current_theme.accent_color.is_some()
+                                    && ui.button("Reset").clicked()
+                                {
+                                    self.current_theme.accent_color = None;
+                                    self.update_current_theme();
+                                }
+                            });
 
-                        // Theme selection
-                        ui.heading("Theme");
-                        ui.add_space(5.0);
+                            ui.collapsing("Heading Prefixes", |ui| {
+                                let levels = self.current_theme.heading_colors.len();
+                                let prefixes = &mut self.current_theme.heading_prefixes;
+                                prefixes.resize(prefixes.len().max(levels), String::new());
+                                for (index, prefix) in prefixes.iter_mut().take(levels).enumerate()
+                                {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("H{}:", index + 1));
+                                        if ui
+                                            .add(
+                                                egui::TextEdit::singleline(prefix)
+                                                    .desired_width(60.0),
+                                            )
+                                            .changed()
+                                        {
+                                            self.theme_edited = true;
+                                        }
+                                    });
+                                }
+                            });
 
-                        egui::ComboBox::from_label("")
-                            .selected_text(self.current_theme.name.clone())
-                            .show_ui(ui, |ui| {
-                                for theme in &self.available_themes {
+                            // Font size the theme switches to when picked, saved like the colors
+                            ui.horizontal(|ui| {
+                                let mut has_font_size =
+                                    self.current_theme.default_font_size.is_some();
+                                if ui
+                                    .checkbox(&mut has_font_size, "Font Size:")
+                                    .on_hover_text("Switch to this size when the theme is picked")
+                                    .changed()
+                                {
+                                    self.current_theme.default_font_size =
+                                        has_font_size.then_some(self.font_size);
+                                    self.update_current_theme();
+                                }
+                                if let Some(font_size) = &mut self.current_theme.default_font_size {
                                     if ui
-                                        .selectable_label(
-                                            self.current_theme.name == theme.name,
-                                            theme.name.clone(),
+                                        .add(
+                                            egui::DragValue::new(font_size)
+                                                .range(MIN_FONT_SIZE..=MAX_FONT_SIZE)
+                                                .speed(0.5)
+                                                .max_decimals(0)
+                                                .suffix("px"),
                                         )
-                                        .clicked()
+                                        .changed()
                                     {
-                                        self.current_theme = theme.clone();
-                                        // Save theme preference
-                                        if let Err(e) = save_theme_preference(&theme.name) {
-                                            eprintln!("Failed to save theme preference: {}", e);
-                                        }
+                                        self.theme_edited = true;
                                     }
                                 }
                             });
-                    },
-                );
 
-                ui.separator();
+                            ui.add_space(5.0);
+                            ui.horizontal(|ui| {
+                                if ui.button("Import theme…").clicked() {
+                                    self.import_theme();
+                                }
+                                if ui.button("Export current theme…").clicked() {
+                                    self.export_theme();
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                if ui.button("Import config bundle…").clicked() {
+                                    self.import_config_bundle();
+                                }
+                                if ui.button("Export config bundle…").clicked() {
+                                    self.export_config_bundle();
+                                }
+                            });
+
+                            if let Some(status) = &self.theme_status {
+                                match status {
+                                    Ok(message) => ui.label(message),
+                                    Err(message) => ui.colored_label(Color32::RED, message),
+                                };
+                            }
+                        },
+                    );
+
+                    ui.separator();
+                    self.render_controls_toggle(ui, controls_opacity);
+                }
 
                 // Right column - Content panel
                 ui.allocate_ui_with_layout(
@@ -701,134 +7148,269 @@ impl App for MarkPrompter {
                                     .to_string(),
                             );
                         }
+                        if self.file_missing {
+                            self.render_missing_file_banner(ui);
+                        }
+                        if self.is_playing {
+                            self.resumed = false;
+                        }
+                        if self.resumed {
+                            self.render_resume_banner(ui);
+                        }
+
+                        let text_color = theme_text_color;
+
+                        // Calculate time delta for scrolling
+                        let now = Instant::now();
+                        let dt = now.duration_since(self.last_update).as_secs_f32();
+                        self.last_update = now;
+                        self.update_scroll(dt);
+                        self.tick_metronome(dt);
+
+                        // The first frame after idling has a long delta, so each step is capped
+                        // to keep the fade from being skipped
+                        if self.content_fade < 1.0 {
+                            self.content_fade = (self.content_fade
+                                + dt.min(MAX_FADE_STEP) / FADE_IN_DURATION)
+                                .min(1.0);
+                        }
+
+                        // Fill remaining height with scroll area, inside the padding. The
+                        // viewport shrinks with it, so the end of the content still lines up
+                        let padding = self.settings.content_padding;
+                        ui.add_space(padding.top);
+                        let available_size = (ui.available_size()
+                            - egui::vec2(padding.left + padding.right, padding.bottom))
+                        .max(egui::Vec2::ZERO);
+
+                        let columns = self.column_count();
+                        let column_width =
+                            (available_size.x - COLUMN_GAP * (columns - 1) as f32) / columns as f32;
+
+                        let mut scroll_area = ScrollArea::vertical()
+                            .max_height(available_size.y)
+                            .max_width(column_width)
+                            .scroll_bar_visibility(
+                                self.settings.scrollbar.visibility(self.is_playing),
+                            );
+                        let text_width = column_width - self.settings.scrollbar.reserved_width();
+
+                        // Only drive the offset while playing or after a seek, so that
+                        // manual scrolling works the rest of the time
+                        if self.is_playing || self.scroll_requested {
+                            scroll_area = scroll_area.vertical_scroll_offset(self.scroll_position);
+                            self.scroll_requested = false;
+                        }
+
+                        // Text rewraps when the width changes, so keep our place as for
+                        // font size changes
+                        if text_width != self.content_width {
+                            if self.content_width > 0.0 {
+                                self.preserve_scroll_fraction();
+                            }
+                            self.content_width = text_width;
+                        }
+
+                        let columns_response = ui.horizontal_top(|ui| {
+                            ui.multiply_opacity(self.content_fade);
+                            ui.add_space(padding.left);
+                            ui.spacing_mut().item_spacing.x = COLUMN_GAP;
+                            if self.settings.themed_scrollbar {
+                                style_scrollbar(ui, text_color, bg_color);
+                            }
+                            let output = scroll_area.show(ui, |ui| {
+                                ui.set_width(text_width);
+                                self.render_column(
+                                    ui,
+                                    text_color,
+                                    text_color_z,
+                                    available_size.y,
+                                    true,
+                                );
+                            });
+
+                            // The second column follows on a screen below the first and only
+                            // moves with it. Blank space after its copy of the content lets it
+                            // run past the end instead of stopping alongside the first
+                            if columns > 1 {
+                                ScrollArea::vertical()
+                                    .id_salt("second_column")
+                                    .max_height(available_size.y)
+                                    .max_width(column_width)
+                                    .scroll_source(egui::scroll_area::ScrollSource::NONE)
+                                    .scroll_bar_visibility(
+                                        egui::scroll_area::ScrollBarVisibility::AlwaysHidden,
+                                    )
+                                    .vertical_scroll_offset(
+                                        output.state.offset.y + output.inner_rect.height(),
+                                    )
+                                    .show(ui, |ui| {
+                                        ui.set_width(text_width);
+                                        self.render_column(
+                                            ui,
+                                            text_color,
+                                            text_color_z,
+                                            available_size.y,
+                                            false,
+                                        );
+                                        ui.add_space(output.inner_rect.height());
+                                    });
+                            }
+                            output
+                        });
+                        let output = columns_response.inner;
+                        let panel_rect = columns_response.response.rect;
+
+                        // Adopt the ScrollArea's offset so wheel, drag and scrollbar input
+                        // (applied on top of any commanded offset) moves our position too
+                        self.adopt_scroll_offset(output.state.offset.y);
+                        self.content_height = output.content_size.y;
+                        self.viewport_height = output.inner_rect.height();
+
+                        // After a relayout, move to the same fraction of the new height. Heading
+                        // pauses already passed stay passed
+                        if let Some(fraction) = self.scroll_fraction.take() {
+                            self.scroll_position = fraction * self.content_height;
+                            self.scroll_requested = true;
+                            self.rearm_pauses();
+                        }
+
+                        // Wall clock overlay in the top-right corner of the content panel
+                        let painter = ui.painter_at(panel_rect);
+                        if self.settings.show_clock {
+                            let format = if self.settings.clock_24_hour {
+                                "%H:%M:%S"
+                            } else {
+                                "%I:%M:%S %p"
+                            };
+                            let time = chrono::Local::now().format(format).to_string();
+                            paint_badge(
+                                &painter,
+                                egui::Align2::RIGHT_TOP,
+                                panel_rect.right_top() + egui::vec2(-8.0, 8.0),
+                                time,
+                                text_color,
+                                bg_color,
+                            );
+                        }
+
+                        // Title and action safe guides for framing the prompter on camera
+                        if self.settings.show_safe_areas {
+                            paint_safe_areas(
+                                &painter,
+                                panel_rect,
+                                &self.settings.safe_areas,
+                                text_color.gamma_multiply(0.5),
+                            );
+                        }
+
+                        // The new speed, briefly, after it's changed with the wheel
+                        if let Some(changed) = self.speed_readout {
+                            if changed.elapsed().as_secs_f32() < SPEED_READOUT_DURATION {
+                                paint_badge(
+                                    &painter,
+                                    egui::Align2::CENTER_BOTTOM,
+                                    panel_rect.center_bottom() + egui::vec2(0.0, -16.0),
+                                    format!(
+                                        "Speed {}",
+                                        self.settings.speed_unit.format(self.scroll_speed)
+                                    ),
+                                    text_color,
+                                    bg_color,
+                                );
+                            } else {
+                                self.speed_readout = None;
+                            }
+                        }
+
+                        // Quiet countdown in the top-left corner while paused at a heading
+                        if let Some(remaining) = self.current_pause {
+                            paint_badge(
+                                &painter,
+                                egui::Align2::LEFT_TOP,
+                                panel_rect.left_top() + egui::vec2(8.0, 8.0),
+                                format!("Pausing… {:.1}s", remaining.max(0.0)),
+                                text_color.gamma_multiply(0.7),
+                                bg_color,
+                            );
+                        }
 
-                        let text_color = Color32::from_rgb(
-                            self.current_theme.text_color[0],
-                            self.current_theme.text_color[1],
-                            self.current_theme.text_color[2],
-                        );
+                        // And until kiosk mode starts playing after a file loads
+                        if let Some(remaining) = self.kiosk_countdown() {
+                            paint_badge(
+                                &painter,
+                                egui::Align2::LEFT_TOP,
+                                panel_rect.left_top() + egui::vec2(8.0, 8.0),
+                                format!("Starting in {:.0}s", remaining.ceil()),
+                                text_color.gamma_multiply(0.7),
+                                bg_color,
+                            );
+                        }
 
-                        // Fill remaining height with scroll area
-                        let available_size = ui.available_size();
-                        let scroll_area = ScrollArea::vertical()
-                            .max_height(available_size.y)
-                            .max_width(available_size.x)
-                            .vertical_scroll_offset(self.scroll_position);
-
-                        let output = scroll_area.show(ui, |ui| {
-                            ui.set_width(available_size.x - 20.0); // Account for scrollbar
-
-                            // Calculate time delta for scrolling
-                            let now = Instant::now();
-                            let dt = now.duration_since(self.last_update).as_secs_f32();
-                            self.last_update = now;
-                            self.update_scroll(dt);
-
-                            if !self.parsed_content.is_empty() {
-                                // Custom markdown rendering with colored headings
-                                let lines = self.content.lines().collect::<Vec<&str>>();
-
-                                egui::Grid::new("markdown_content")
-                                    .num_columns(1)
-                                    .spacing([0.0, 5.0])
-                                    .striped(false)
-                                    .show(ui, |ui| {
-                                        for line in lines.iter() {
-                                            let trimmed = line.trim();
-
-                                            // Detect heading level and extract text without #
-                                            let mut heading_level = 0;
-                                            let display_text = if trimmed.starts_with("# ") {
-                                                heading_level = 1;
-                                                trimmed.trim_start_matches("# ")
-                                            } else if trimmed.starts_with("## ") {
-                                                heading_level = 2;
-                                                trimmed.trim_start_matches("## ")
-                                            } else if trimmed.starts_with("### ") {
-                                                heading_level = 3;
-                                                trimmed.trim_start_matches("### ")
-                                            } else if trimmed.starts_with("#### ") {
-                                                heading_level = 4;
-                                                trimmed.trim_start_matches("#### ")
-                                            } else if trimmed.starts_with("##### ") {
-                                                heading_level = 5;
-                                                trimmed.trim_start_matches("##### ")
-                                            } else if trimmed.starts_with("###### ") {
-                                                heading_level = 6;
-                                                trimmed.trim_start_matches("###### ")
-                                            } else {
-                                                *line
-                                            };
-
-                                            // Apply appropriate color and styling based on whether it's a heading
-                                            if heading_level > 0
-                                                && heading_level
-                                                    <= self.current_theme.heading_colors.len()
-                                            {
-                                                // It's a heading - use the appropriate heading color
-                                                let idx = heading_level - 1;
-                                                let heading_color = Color32::from_rgb(
-                                                    self.current_theme.heading_colors[idx][0],
-                                                    self.current_theme.heading_colors[idx][1],
-                                                    self.current_theme.heading_colors[idx][2],
-                                                );
-
-                                                // Adjust font size based on heading level
-                                                // H1: 2.0x, H2: 1.8x, H3: 1.6x, H4: 1.4x, H5: 1.2x, H6: 1.1x
-                                                // let size_multipliers = [2.0, 1.8, 1.6, 1.4, 1.2, 1.1];
-                                                let size_multipliers =
-                                                    [2.0, 1.8, 1.6, 1.4, 1.2, 1.1];
-                                                let heading_size =
-                                                    self.font_size * size_multipliers[idx];
-                                                ui.style_mut()
-                                                    .text_styles
-                                                    .get_mut(&egui::TextStyle::Body)
-                                                    .unwrap()
-                                                    .size = heading_size;
-
-                                                ui.colored_label(heading_color, display_text);
-                                                ui.end_row();
-
-                                                // Reset font size to default
-                                                ui.style_mut()
-                                                    .text_styles
-                                                    .get_mut(&egui::TextStyle::Body)
-                                                    .unwrap()
-                                                    .size = self.font_size;
-                                            } else {
-                                                // Regular text - use the formatted text renderer
-                                                self.render_formatted_text(
-                                                    ui,
-                                                    display_text,
-                                                    text_color,
-                                                    self.font_size,
-                                                );
-                                                ui.end_row();
-                                            }
-                                        }
-                                    });
+                        // Handle end-of-content scrolling behavior
+                        if !self.is_playing {
+                            self.loop_wait = None;
+                        }
+                        // The practice range, when there is one, stands in for the script
+                        let (start_position, max_position) = self.playback_bounds();
+                        if self.is_playing && self.reversing {
+                            // Back at the top, so head down again with every pause re-armed
+                            if self.scroll_position <= start_position {
+                                self.reversing = false;
+                                self.seek_to(start_position);
+                            }
+                        } else if self.is_playing && self.scroll_position >= max_position {
+                            if self.end_behavior() == EndBehavior::Loop {
+                                self.scroll_position = max_position;
+                                let wait = self.loop_wait.unwrap_or(self.settings.loop_delay);
+                                if wait - dt <= 0.0 {
+                                    self.loop_wait = None;
+                                    self.restart_loop(start_position);
+                                } else {
+                                    self.loop_wait = Some(wait - dt);
+                                }
+                            } else if self.end_behavior() == EndBehavior::Hold {
+                                self.scroll_position = max_position;
+                            } else if self.end_behavior() == EndBehavior::PingPong {
+                                self.scroll_position = max_position;
+                                self.reversing = true;
+                            } else if self.settings.playlist_auto_advance
+                                && self.practice_bounds().is_none()
+                                && self.step_playlist(true)
+                            {
+                                // Carry on playing into the next script
                             } else {
-                                ui.colored_label(
-                                    text_color_z,
-                                    RichText::new("Open a markdown file to begin.").size(48.0),
-                                );
+                                self.scroll_position = max_position;
+                                self.is_playing = false;
+                                self.end_reached = Some(Instant::now());
+                                self.report_end();
+                                if self.settings.end_of_script_sound {
+                                    self.sounds.beep();
+                                }
                             }
-                        });
+                        }
 
-                        // Handle end-of-content scrolling behavior
+                        // Notice in the middle of the panel when playback stops at the end,
+                        // fading out over a few seconds
                         if self.is_playing {
-                            let content_height = output.inner_rect.height();
-                            let available_height = ui.available_height();
-
-                            if self.scroll_position >= content_height - available_height {
-                                if self.auto_restart {
-                                    self.scroll_position = 0.0;
-                                    self.last_checked_heading_idx = 0;
-                                } else {
-                                    self.scroll_position =
-                                        (content_height - available_height).max(0.0);
-                                    self.is_playing = false;
-                                }
+                            self.end_reached = None;
+                        }
+                        if let Some(reached) = self.end_reached {
+                            let elapsed = reached.elapsed().as_secs_f32();
+                            if elapsed < END_NOTICE_DURATION {
+                                let fade = (END_NOTICE_DURATION - elapsed).min(1.0);
+                                let galley = painter.layout_no_wrap(
+                                    "End of script".to_string(),
+                                    egui::FontId::proportional(40.0),
+                                    text_color.gamma_multiply(fade),
+                                );
+                                let rect = egui::Align2::CENTER_CENTER
+                                    .anchor_size(panel_rect.center(), galley.size())
+                                    .expand(16.0);
+                                painter.rect_filled(rect, 8.0, bg_color.gamma_multiply(0.9 * fade));
+                                painter.galley(rect.shrink(16.0).min, galley, text_color);
+                            } else {
+                                self.end_reached = None;
                             }
                         }
                     },
@@ -836,27 +7418,361 @@ impl App for MarkPrompter {
             });
         });
 
-        // Request continuous repaint to enable smooth scrolling
-        ctx.request_repaint();
+        self.show_theme_conflicts(ctx);
+        self.report_state_changes();
+        self.update_cursor_visibility(ctx);
+        self.update_text_rounding(ctx);
+
+        // Wait until drags finish so per-file settings and theme edits aren't written every frame
+        if !ctx.input(|i| i.pointer.any_down()) {
+            self.store_file_settings();
+            if self.theme_edited {
+                self.theme_edited = false;
+                self.update_current_theme();
+            }
+        }
+
+        // Repaint continuously only while scrolling, counting down a heading pause, fading
+        // in new content, showing the wheel speed readout or fading out the end of script
+        // notice, so the UI idles when stopped. The clock overlay still ticks over once a second
+        if self.is_playing
+            || self.end_reached.is_some()
+            || self.content_fade < 1.0
+            || self.speed_readout.is_some()
+        {
+            ctx.request_repaint();
+        } else if self.settings.show_clock {
+            let millis = chrono::Local::now().timestamp_subsec_millis().min(999);
+            ctx.request_repaint_after(Duration::from_millis(1000 - millis as u64));
+        }
+    }
+}
+
+// Size of the color preview beside each theme name in the theme selector
+const THEME_SWATCH_SIZE: egui::Vec2 = egui::vec2(36.0, 14.0);
+
+// Preview a theme as its background with stripes of its text color and first few heading colors
+fn paint_theme_swatch(painter: &egui::Painter, rect: egui::Rect, theme: &Theme) {
+    let [r, g, b] = theme.background_color;
+    painter.rect_filled(rect, 2.0, Color32::from_rgb(r, g, b));
+    painter.rect_stroke(
+        rect,
+        2.0,
+        egui::Stroke::new(1.0, Color32::GRAY),
+        egui::StrokeKind::Inside,
+    );
+
+    let colors: Vec<[u8; 3]> = std::iter::once(theme.text_color)
+        .chain(theme.heading_colors.iter().take(3).copied())
+        .collect();
+    let inner = rect.shrink(3.0);
+    let stripe_width = inner.width() / colors.len() as f32;
+    for (i, [r, g, b]) in colors.into_iter().enumerate() {
+        let left = inner.left() + i as f32 * stripe_width;
+        let stripe =
+            egui::Rect::from_x_y_ranges(left + 1.0..=left + stripe_width - 1.0, inner.y_range());
+        painter.rect_filled(stripe, 1.0, Color32::from_rgb(r, g, b));
+    }
+}
+
+// Word count and reading time for a section of the outline, like "120 words · 0:45"
+fn section_summary(words: usize, seconds: Option<f32>) -> String {
+    match seconds {
+        Some(seconds) => {
+            let seconds = seconds.round() as u64;
+            format!("{} words · {}:{:02}", words, seconds / 60, seconds % 60)
+        }
+        None => format!("{} words", words),
+    }
+}
+
+// One state event per line on stdout, which carries nothing else: logs go to stderr
+fn print_state_event(event: serde_json::Value) {
+    println!("{}", event);
+}
+
+// Slider and exact value for how long a pause lasts
+fn pause_duration_row(ui: &mut egui::Ui, duration: &mut f32) {
+    ui.horizontal(|ui| {
+        ui.label("Duration:");
+        let range = MIN_HEADING_PAUSE..=MAX_HEADING_PAUSE;
+        ui.add(
+            egui::Slider::new(duration, range.clone())
+                .suffix("s")
+                .text("sec"),
+        );
+        ui.add(
+            egui::DragValue::new(duration)
+                .range(range)
+                .speed(0.1)
+                .max_decimals(1)
+                .suffix("s"),
+        );
+    });
+}
+
+// Color the scrollbars in a Ui from the theme: a handle of the text color on a track of the
+// background. Egui picks the handle from either the fill or the stroke, so both are set
+fn style_scrollbar(ui: &mut egui::Ui, text_color: Color32, bg_color: Color32) {
+    let visuals = ui.visuals_mut();
+    visuals.extreme_bg_color = bg_color;
+    for (widget, strength) in [
+        (&mut visuals.widgets.inactive, 0.35),
+        (&mut visuals.widgets.hovered, 0.55),
+        (&mut visuals.widgets.active, 0.75),
+    ] {
+        widget.bg_fill = text_color.gamma_multiply(strength);
+        widget.fg_stroke.color = text_color.gamma_multiply(strength);
+    }
+}
+
+// Outline each safe area as a dashed rectangle inset from the panel to the given percentage
+// of its size, and label it in its top-left corner
+fn paint_safe_areas(
+    painter: &egui::Painter,
+    panel: egui::Rect,
+    percentages: &[f32],
+    color: Color32,
+) {
+    let stroke = egui::Stroke::new(1.0, color);
+    for &percent in percentages {
+        let rect = panel.shrink2(panel.size() * (1.0 - percent / 100.0) / 2.0);
+        let corners = [
+            rect.left_top(),
+            rect.right_top(),
+            rect.right_bottom(),
+            rect.left_bottom(),
+            rect.left_top(),
+        ];
+        painter.extend(egui::Shape::dashed_line(&corners, stroke, 8.0, 6.0));
+        painter.text(
+            rect.left_top() + egui::vec2(4.0, 4.0),
+            egui::Align2::LEFT_TOP,
+            format!("{:.0}%", percent),
+            egui::FontId::monospace(12.0),
+            color,
+        );
+    }
+}
+
+// Character range of the sentence around a character: from after the previous ".", "!" or "?"
+// that's followed by a space, to the next one, along with any closing quotes or brackets
+// after it. Spaces at the start are left out
+fn sentence_around(text: &str, index: usize) -> std::ops::Range<usize> {
+    let chars: Vec<char> = text.chars().collect();
+    let ends_sentence = |i: usize| {
+        let mut end = i;
+        while end < chars.len() && ['.', '!', '?'].contains(&chars[end]) {
+            end += 1;
+        }
+        if end == i {
+            return None;
+        }
+        while end < chars.len() && ['"', '\'', '”', '’', ')', ']'].contains(&chars[end]) {
+            end += 1;
+        }
+        (end == chars.len() || chars[end].is_whitespace()).then_some(end)
+    };
+
+    let mut start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        if let Some(end) = ends_sentence(i) {
+            if end > index {
+                return start..end;
+            }
+            start = end;
+            i = end;
+        } else {
+            i += 1;
+        }
+        while start < chars.len() && start < i && chars[start].is_whitespace() {
+            start += 1;
+        }
+    }
+    start..chars.len()
+}
+
+// Opacity of a row a given distance from the focus line, in multiples of the spotlight's
+// reach: full at the line, fading by up to `strength` at the reach and beyond
+fn spotlight_opacity(distance: f32, strength: f32) -> f32 {
+    1.0 - strength * distance.clamp(0.0, 1.0)
+}
+
+// Paint a small label on a translucent background over the content panel
+fn paint_badge(
+    painter: &egui::Painter,
+    anchor: egui::Align2,
+    pos: egui::Pos2,
+    text: String,
+    text_color: Color32,
+    bg_color: Color32,
+) {
+    let galley = painter.layout_no_wrap(text, egui::FontId::monospace(16.0), text_color);
+    let rect = anchor.anchor_size(pos, galley.size()).expand(4.0);
+    painter.rect_filled(rect, 4.0, bg_color.gamma_multiply(0.8));
+    painter.galley(rect.shrink(4.0).min, galley, text_color);
+}
+
+// Set up egui's fonts: the defaults, with the material icons after the proportional fonts and
+// a family for code that puts the chosen font file ahead of the default monospace fonts. A
+// file that isn't a usable font is left out and reported. Emoji glyphs come from the Noto
+// Emoji font that egui's defaults register as a fallback for proportional text
+fn install_fonts(
+    ctx: &egui::Context,
+    code_font: Option<&std::path::Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use egui::{FontData, FontDefinitions, FontFamily};
+    use std::sync::Arc;
+
+    let mut fonts = FontDefinitions::default();
+    let icons = egui_material_icons::font_insert();
+    fonts
+        .font_data
+        .insert(icons.name.clone(), Arc::new(icons.data));
+    fonts
+        .families
+        .entry(FontFamily::Proportional)
+        .or_default()
+        .push(icons.name);
+
+    let mut code_fonts = fonts
+        .families
+        .get(&FontFamily::Monospace)
+        .cloned()
+        .unwrap_or_default();
+    let mut result = Ok(());
+    if let Some(path) = code_font {
+        result = fs::read(path)
+            .map_err(Into::into)
+            .and_then(|bytes| match ab_glyph::FontRef::try_from_slice(&bytes) {
+                Ok(_) => Ok(bytes),
+                Err(e) => Err(Box::new(e) as Box<dyn std::error::Error>),
+            })
+            .map(|bytes| {
+                fonts.font_data.insert(
+                    CODE_FONT_FAMILY.to_string(),
+                    Arc::new(FontData::from_owned(bytes)),
+                );
+                code_fonts.insert(0, CODE_FONT_FAMILY.to_string());
+            });
+    }
+    fonts
+        .families
+        .insert(FontFamily::Name(CODE_FONT_FAMILY.into()), code_fonts);
+    ctx.set_fonts(fonts);
+    result
+}
+
+// Read a script as text. A UTF-8 byte order mark is dropped, and a file that isn't valid
+// UTF-8 is still loaded: as UTF-16 if it starts with a UTF-16 byte order mark, otherwise as
+// Latin-1, which every byte decodes as. The second value says when that happened
+fn read_script(path: &std::path::Path) -> std::io::Result<(String, Option<String>)> {
+    let bytes = fs::read(path)?;
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok((text.to_string(), None));
     }
+
+    let utf16 = |rest: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = rest
+            .chunks(2)
+            .map(|pair| from_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+    let (text, encoding) = if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        (utf16(rest, u16::from_le_bytes), "UTF-16")
+    } else if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        (utf16(rest, u16::from_be_bytes), "UTF-16")
+    } else {
+        (
+            bytes.iter().map(|&byte| char::from(byte)).collect(),
+            "Latin-1",
+        )
+    };
+    eprintln!(
+        "{} isn't valid UTF-8, reading it as {}",
+        path.display(),
+        encoding
+    );
+    Ok((
+        text,
+        Some(format!(
+            "This file isn't valid UTF-8, so it was read as {}. Some characters may be shown wrongly",
+            encoding
+        )),
+    ))
+}
+
+// Poll a file for changes once a second, sending an event when it's modified and stopping
+// once it's gone or the watcher is dropped, like after opening another file
+fn watch_file(path: PathBuf, ctx: egui::Context) -> FileWatcher {
+    let (tx, rx) = channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    thread::spawn(move || {
+        let mut last_modified = fs::metadata(&path).ok().and_then(|m| m.modified().ok());
+
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            if stopped.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    // Editors that save by renaming leave the path missing briefly
+                    thread::sleep(Duration::from_secs(1));
+                    match fs::metadata(&path) {
+                        Ok(metadata) => metadata,
+                        Err(_) => {
+                            let _ = tx.send(WatchEvent::Deleted);
+                            ctx.request_repaint();
+                            break;
+                        }
+                    }
+                }
+            };
+            if let Ok(modified) = metadata.modified() {
+                if let Some(last) = last_modified {
+                    if modified > last {
+                        if tx.send(WatchEvent::Modified).is_err() {
+                            break;
+                        }
+                        ctx.request_repaint();
+                        last_modified = Some(modified);
+                    }
+                } else {
+                    last_modified = Some(modified);
+                }
+            }
+        }
+    });
+    FileWatcher { events: rx, stop }
 }
 
 // Save theme preference to themes.toml
 fn save_theme_preference(theme_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = "themes.toml";
-
     // Read the current themes
     let themes = load_themes_without_preference()?;
+    save_themes(&themes, theme_name)
+}
+
+// Save the theme list and selected theme to themes.toml
+fn save_themes(themes: &[Theme], selected_theme: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = THEMES_PATH;
 
     // Create the config structure with preference
     #[derive(Serialize)]
-    struct ThemesConfigWithPreference {
-        selected_theme: String,
-        themes: Vec<Theme>,
+    struct ThemesConfigWithPreference<'a> {
+        selected_theme: &'a str,
+        themes: &'a [Theme],
     }
 
     let config = ThemesConfigWithPreference {
-        selected_theme: theme_name.to_string(),
+        selected_theme,
         themes,
     };
 
@@ -865,15 +7781,120 @@ fn save_theme_preference(theme_name: &str) -> Result<(), Box<dyn std::error::Err
     Ok(())
 }
 
+// Load a single theme from a TOML or JSON file
+fn load_theme_file(path: &std::path::Path) -> Result<Theme, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    let theme: Theme = if is_json_path(path) {
+        serde_json::from_str(&contents)?
+    } else {
+        toml::from_str(&contents)?
+    };
+    validate_theme(&theme)?;
+    Ok(theme)
+}
+
+// Reject themes that can't be shown or selected
+fn validate_theme(theme: &Theme) -> Result<(), Box<dyn std::error::Error>> {
+    if theme.name.trim().is_empty() {
+        return Err("theme has no name".into());
+    }
+    if theme.heading_colors.is_empty() {
+        return Err(format!("theme \"{}\" has no heading colors", theme.name).into());
+    }
+    Ok(())
+}
+
+// Settings and themes packed into one file for moving between machines
+#[derive(Serialize, Deserialize)]
+struct ConfigBundle {
+    settings: Settings,
+    themes: Vec<Theme>,
+}
+
+// A change to the playlist picked in its list
+enum PlaylistEdit {
+    Open(usize),
+    Swap(usize, usize),
+    Remove(usize),
+}
+
+// What to do with an imported theme whose name is already taken
+enum ThemeConflictChoice {
+    Replace,
+    KeepBoth,
+    Skip,
+}
+
+// Load a config bundle, checking every theme in it
+fn load_config_bundle(path: &std::path::Path) -> Result<ConfigBundle, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    let bundle: ConfigBundle = toml::from_str(&contents)?;
+    for theme in &bundle.themes {
+        validate_theme(theme)?;
+    }
+    Ok(bundle)
+}
+
+fn save_config_bundle(
+    path: &std::path::Path,
+    bundle: &ConfigBundle,
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::write(path, toml::to_string(bundle)?)?;
+    Ok(())
+}
+
+// Save a single theme to a TOML or JSON file
+fn save_theme_file(
+    path: &std::path::Path,
+    theme: &Theme,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = if is_json_path(path) {
+        serde_json::to_string_pretty(theme)?
+    } else {
+        toml::to_string(theme)?
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+// File extensions opened as markdown scripts
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+fn is_markdown_path(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|ext| {
+        MARKDOWN_EXTENSIONS
+            .iter()
+            .any(|markdown| ext.eq_ignore_ascii_case(markdown))
+    })
+}
+
+fn is_json_path(path: &std::path::Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// Add a " (2)"-style suffix if a theme with this name already exists
+fn unique_theme_name(themes: &[Theme], name: &str) -> String {
+    let name = name.trim();
+    let taken = |candidate: &str| themes.iter().any(|t| t.name == candidate);
+    if !taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| format!("{} ({})", name, n))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
 // Load themes and preference from a TOML file
 fn load_themes_and_preference() -> Result<(Vec<Theme>, Option<String>), Box<dyn std::error::Error>>
 {
-    let config_path = "themes.toml";
+    let config_path = THEMES_PATH;
     if !std::path::Path::new(config_path).exists() {
         // Create a default theme file if it doesn't exist
         let default_themes = create_default_themes();
 
-        println!("Attempting to create themes.toml file...");
+        eprintln!("Attempting to create themes.toml file...");
 
         // Wrap themes in a structure for TOML serialization
         #[derive(Serialize)]
@@ -888,9 +7909,9 @@ fn load_themes_and_preference() -> Result<(Vec<Theme>, Option<String>), Box<dyn
         };
 
         let toml_string = toml::to_string(&config)?;
-        println!("TOML string generated successfully");
+        eprintln!("TOML string generated successfully");
         fs::write(config_path, toml_string)?;
-        println!("themes.toml file created successfully");
+        eprintln!("themes.toml file created successfully");
         return Ok((default_themes, None));
     }
 
@@ -925,6 +7946,26 @@ fn load_themes_without_preference() -> Result<Vec<Theme>, Box<dyn std::error::Er
     Ok(themes)
 }
 
+// Load application settings from settings.toml, using defaults if it doesn't exist
+fn load_settings() -> Result<Settings, Box<dyn std::error::Error>> {
+    let config_path = "settings.toml";
+    if !std::path::Path::new(config_path).exists() {
+        return Ok(Settings::default());
+    }
+
+    let toml_str = fs::read_to_string(config_path)?;
+    let settings: Settings = toml::from_str(&toml_str)?;
+    Ok(settings)
+}
+
+// Save application settings to settings.toml
+fn save_settings(settings: &Settings) -> Result<(), Box<dyn std::error::Error>> {
+    let config_path = "settings.toml";
+    let toml_string = toml::to_string(settings)?;
+    fs::write(config_path, toml_string)?;
+    Ok(())
+}
+
 // Helper function to create default themes
 fn create_default_themes() -> Vec<Theme> {
     vec![
@@ -940,6 +7981,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [60, 60, 140],   // H5
                 [50, 50, 130],   // H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Dark".to_string(),
@@ -953,6 +7995,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [170, 100, 60],  // H5
                 [150, 80, 50],   // H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Solarized".to_string(),
@@ -966,6 +8009,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [108, 113, 196], // H5
                 [38, 139, 210],  // H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "After Dark".to_string(),
@@ -979,6 +8023,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [167, 243, 208], // success: #a7f3d0 - H5
                 [254, 240, 138], // warning: #fef08a - H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Her".to_string(),
@@ -992,6 +8037,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [167, 243, 208], // success: #a7f3d0 - H5
                 [254, 240, 138], // warning: #fef08a - H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Forest".to_string(),
@@ -1005,6 +8051,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [167, 243, 208], // success: #a7f3d0 - H5
                 [254, 240, 138], // warning: #fef08a - H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Sky".to_string(),
@@ -1018,6 +8065,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [254, 240, 138], // warning: #fef08a - H5
                 [252, 165, 165], // error: #fca5a5 - H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Clays".to_string(),
@@ -1031,6 +8079,7 @@ fn create_default_themes() -> Vec<Theme> {
                 [167, 243, 208], // success: #a7f3d0 - H5
                 [254, 240, 138], // warning: #fef08a - H6
             ],
+            ..Default::default()
         },
         Theme {
             name: "Stones".to_string(),
@@ -1044,21 +8093,496 @@ fn create_default_themes() -> Vec<Theme> {
                 [167, 243, 208], // success: #a7f3d0 - H5
                 [254, 240, 138], // warning: #fef08a - H6
             ],
+            ..Default::default()
+        },
+        // The last two tell heading levels apart by more than hue: each level is a clearly
+        // different size and carries a decoration that shrinks with it
+        Theme {
+            name: "High Contrast".to_string(),
+            background_color: [0, 0, 0],
+            text_color: [255, 255, 255],
+            heading_colors: vec![
+                [255, 255, 0],   // H1
+                [0, 255, 255],   // H2
+                [255, 255, 255], // H3
+                [255, 255, 0],   // H4
+                [0, 255, 255],   // H5
+                [255, 255, 255], // H6
+            ],
+            heading_decoration: HeadingDecoration::Underline,
+            ..Default::default()
+        },
+        Theme {
+            name: "Color Blind Safe".to_string(),
+            background_color: [30, 30, 30],
+            text_color: [230, 230, 230],
+            heading_colors: vec![
+                [230, 159, 0],   // Okabe-Ito orange: #E69F00 - H1
+                [86, 180, 233],  // sky blue: #56B4E9 - H2
+                [240, 228, 66],  // yellow: #F0E442 - H3
+                [0, 158, 115],   // bluish green: #009E73 - H4
+                [204, 121, 167], // reddish purple: #CC79A7 - H5
+                [213, 94, 0],    // vermillion: #D55E00 - H6
+            ],
+            heading_decoration: HeadingDecoration::AccentBar,
+            ..Default::default()
         },
     ]
 }
 
 fn main() -> Result<(), eframe::Error> {
+    // `markprompter script.md` opens the script straight away, and `--theme <name>` picks the
+    // theme for this session
+    let mut initial_file = None;
+    let mut theme = None;
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let arg_str = arg.to_string_lossy();
+        if arg_str == "--theme" {
+            theme = args.next().map(|name| name.to_string_lossy().into_owned());
+            if theme.is_none() {
+                eprintln!("--theme needs a theme name");
+            }
+        } else if let Some(name) = arg_str.strip_prefix("--theme=") {
+            theme = Some(name.to_string());
+        } else if initial_file.is_none() {
+            initial_file = Some(PathBuf::from(arg));
+        }
+    }
+
+    // Reopen the window where it was when the app last closed
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_inner_size([1200.0, 800.0])
+        .with_min_inner_size([800.0, 600.0]);
+    if let Some(window) = load_settings().ok().and_then(|settings| settings.window) {
+        viewport = viewport
+            .with_inner_size(window.size)
+            .with_position(window.position);
+    }
+
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default()
-            .with_inner_size([1200.0, 800.0])
-            .with_min_inner_size([800.0, 600.0]),
+        viewport,
         ..Default::default()
     };
 
     eframe::run_native(
         "MarkPrompter",
         options,
-        Box::new(|cc| Ok(Box::new(MarkPrompter::new(cc)))),
+        Box::new(|cc| Ok(Box::new(MarkPrompter::new(cc, initial_file, theme)))),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Render a few frames of a synthetic 10,000-line script and check that only the rows
+    // near the viewport are laid out, while every heading still gets an offset
+    #[test]
+    fn large_document_renders_only_visible_rows() {
+        let content = (0..10_000)
+            .map(|i| {
+                if i % 50 == 0 {
+                    format!("## Section {}", i / 50)
+                } else {
+                    format!("Line {} of the script, with **bold** and *italic* text", i)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut app = MarkPrompter {
+            content,
+            ..Default::default()
+        };
+        app.parse_markdown();
+
+        let ctx = egui::Context::default();
+        let input = || egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(1200.0, 800.0),
+            )),
+            ..Default::default()
+        };
+
+        let mut laid_out = 0;
+        for _ in 0..20 {
+            let _ = ctx.run(input(), |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    ScrollArea::vertical().show(ui, |ui| {
+                        laid_out = app.render_markdown(ui, Color32::WHITE, true);
+                    });
+                });
+            });
+        }
+        assert!(laid_out > 0);
+        assert!(laid_out < 200, "laid out {} rows", laid_out);
+        assert_eq!(app.row_heights.len(), 10_000);
+        assert_eq!(app.heading_offsets.len(), 200);
+        assert!(app.heading_offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    const SAMPLE: &str = "# Title\nFirst line\n\n\n## Part two\n<!-- pause:2 -->\nMore text";
+
+    fn metrics() -> LayoutMetrics {
+        LayoutMetrics {
+            font_size: 20.0,
+            min_row_height: 18.0,
+            paragraph_gap: 1.0,
+            heading_levels: 6,
+        }
+    }
+
+    fn blocks(content: &str, newlines_as_breaks: bool) -> Vec<RenderBlock> {
+        parse_blocks(content, newlines_as_breaks, false)
+            .into_iter()
+            .map(|(_, block)| block)
+            .collect()
+    }
+
+    fn assert_offsets(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 0.01, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    // With nothing laid out yet, rows get their estimated heights: 52 for the H1, 26 for a
+    // line of text, 20 for the blank lines and 46.8 for the H2, each followed by the row spacing
+    #[test]
+    fn heading_offsets_from_estimated_heights() {
+        let layout = layout_blocks(&blocks(SAMPLE, true), &[], &metrics());
+        assert_offsets(&layout.heading_offsets, &[0.0, 113.0]);
+        assert_eq!(layout.pause_cues.len(), 1);
+        assert_offsets(&[layout.pause_cues[0].0], &[164.8]);
+        assert_eq!(layout.pause_cues[0].1, 2.0);
+        assert_offsets(&layout.paragraph_offsets, &[113.0]);
+        assert_eq!(layout.heights.len(), 6);
+    }
+
+    #[test]
+    fn measured_heights_replace_estimates() {
+        let layout = layout_blocks(&blocks(SAMPLE, true), &[60.0, 10.0], &metrics());
+        // The text row is held to the minimum row height
+        assert_offsets(
+            &layout.heading_offsets,
+            &[0.0, 60.0 + 18.0 + 20.0 + 3.0 * ROW_SPACING],
+        );
+    }
+
+    #[test]
+    fn headings_past_the_theme_levels_are_text() {
+        let metrics = LayoutMetrics {
+            heading_levels: 1,
+            ..metrics()
+        };
+        let layout = layout_blocks(&blocks(SAMPLE, true), &[], &metrics);
+        assert_offsets(&layout.heading_offsets, &[0.0]);
+    }
+
+    #[test]
+    fn word_counts_skip_markup() {
+        assert_eq!(
+            count_words("## Part two\n\nMore **bold** text, and more."),
+            7
+        );
+        assert_eq!(count_words("# Title\n\n- one two\n- three\n\n---\n"), 4);
+        assert_eq!(count_words(""), 0);
+    }
+
+    // Seeking or scrolling back up re-arms the headings above, and forward skips the ones
+    // passed over, so a heading right at the new position still pauses
+    #[test]
+    fn heading_pauses_follow_the_position_both_ways() {
+        let offsets = [0.0, 100.0, 200.0];
+        assert_eq!(next_offset_idx(&offsets, 0.0), 0);
+        assert_eq!(next_offset_idx(&offsets, 50.0), 1);
+        assert_eq!(next_offset_idx(&offsets, 100.0), 1);
+        assert_eq!(next_offset_idx(&offsets, 250.0), 3);
+
+        let mut app = MarkPrompter {
+            heading_offsets: offsets.to_vec(),
+            ..Default::default()
+        };
+        app.seek_to(250.0);
+        assert_eq!(app.last_checked_heading_idx, 3);
+        app.seek_to(50.0);
+        assert_eq!(app.last_checked_heading_idx, 1);
+
+        // Wheel or scrollbar input moving the ScrollArea back up
+        app.seek_to(250.0);
+        app.adopt_scroll_offset(150.0);
+        assert_eq!(app.last_checked_heading_idx, 2);
+
+        // Playback's own movement leaves the next pause alone
+        app.last_checked_heading_idx = 1;
+        app.adopt_scroll_offset(150.2);
+        assert_eq!(app.last_checked_heading_idx, 1);
+    }
+
+    fn item(depth: usize, marker: &str, text: &str) -> RenderBlock {
+        RenderBlock::ListItem {
+            depth,
+            marker: marker.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    // Nested items get their depth from their indentation and stay on their own rows, even
+    // when lines are joined into paragraphs
+    #[test]
+    fn nested_list_items_keep_their_indentation() {
+        let list = "- Fruit\n  - Apple\n    - Green\n- Vegetables";
+        for newlines_as_breaks in [true, false] {
+            assert_eq!(
+                blocks(list, newlines_as_breaks),
+                [
+                    item(0, "-", "Fruit"),
+                    item(1, "-", "Apple"),
+                    item(2, "-", "Green"),
+                    item(0, "-", "Vegetables"),
+                ]
+            );
+        }
+    }
+
+    // Ordered and unordered lists nest in each other, with tabs counting to the next tab stop
+    // and each level indented past the text of the item it's under
+    #[test]
+    fn mixed_lists_nest_three_levels_deep() {
+        let list = "1. First\n   - Point\n\t * Detail\n     2) Step\n   - Another\n10. Tenth";
+        assert_eq!(
+            blocks(list, true),
+            [
+                item(0, "1.", "First"),
+                item(1, "-", "Point"),
+                item(2, "*", "Detail"),
+                item(2, "2)", "Step"),
+                item(1, "-", "Another"),
+                item(0, "10.", "Tenth"),
+            ]
+        );
+
+        // Items aren't nested in a list that something unindented came between
+        assert_eq!(
+            blocks("- One\n\nText\n  - Two", true),
+            [
+                item(0, "-", "One"),
+                RenderBlock::Gap,
+                RenderBlock::Paragraph("Text".to_string()),
+                item(0, "-", "Two"),
+            ]
+        );
+    }
+
+    // Comments are taken out as notes, while a "<!--" that isn't closed, or is in code, stays
+    #[test]
+    fn notes_are_closed_comments() {
+        assert_eq!(
+            extract_notes("Hello <!-- wave --> there<!---->!"),
+            ("Hello  there!".to_string(), vec!["wave".to_string()])
+        );
+        assert_eq!(
+            extract_notes("Type `<!--` to start <!-- a note -->"),
+            (
+                "Type `<!--` to start ".to_string(),
+                vec!["a note".to_string()]
+            )
+        );
+        assert_eq!(
+            extract_notes("A <!-- b --> c <!-- d"),
+            ("A  c <!-- d".to_string(), vec!["b".to_string()])
+        );
+    }
+
+    // Only a comment that starts a line runs on over the lines below it
+    #[test]
+    fn stray_comment_openers_hide_nothing() {
+        assert_eq!(
+            blocks(
+                "Use <!-- to start\nStill here\n<!-- A\nnote -->\nAfter",
+                true
+            ),
+            [
+                RenderBlock::Paragraph("Use <!-- to start".to_string()),
+                RenderBlock::Paragraph("Still here".to_string()),
+                RenderBlock::Paragraph("After".to_string()),
+            ]
+        );
+    }
+
+    // Sentences end at punctuation followed by a space, taking any closing quote with them
+    #[test]
+    fn sentences_split_at_their_punctuation() {
+        let text = "One. Two! \"Three?\" Pi is 3.14 or so. Four";
+        let sentence = |index| {
+            let range = sentence_around(text, index);
+            text[range].to_string()
+        };
+        assert_eq!(sentence(0), "One.");
+        assert_eq!(sentence(6), "Two!");
+        assert_eq!(sentence(12), "\"Three?\"");
+        assert_eq!(sentence(25), "Pi is 3.14 or so.");
+        assert_eq!(sentence(40), "Four");
+    }
+
+    // An alert takes in the quoted lines under it, while other blockquotes stay as written
+    #[test]
+    fn alerts_take_in_their_quoted_lines() {
+        let script = "> [!warning]\n> Mind the\n> step\n>\n> Twice\nAfter\n> [!TODO]\n> Later";
+        assert_eq!(
+            blocks(script, false),
+            [
+                RenderBlock::Alert {
+                    kind: AlertKind::Warning,
+                    text: "Mind the step\n\nTwice".to_string(),
+                },
+                RenderBlock::Paragraph("After".to_string()),
+                RenderBlock::Paragraph("> [!TODO]".to_string()),
+                RenderBlock::Paragraph("> Later".to_string()),
+            ]
+        );
+
+        // Code ends an alert too, so a quote after it isn't added to the alert
+        let script = "> [!NOTE]\n> Before\n```\ncode\n```\n> Quote";
+        assert_eq!(
+            blocks(script, true),
+            [
+                RenderBlock::Alert {
+                    kind: AlertKind::Note,
+                    text: "Before".to_string(),
+                },
+                RenderBlock::CodeBlock {
+                    language: String::new(),
+                    code: "code".to_string(),
+                },
+                RenderBlock::Paragraph("> Quote".to_string()),
+            ]
+        );
+    }
+
+    fn heading(level: usize, text: &str) -> RenderBlock {
+        RenderBlock::Heading {
+            level,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn hashes_need_whitespace_after_them() {
+        assert_eq!(
+            blocks("#no-space\n#\tTabbed\n# Spaced", true),
+            [
+                RenderBlock::Paragraph("#no-space".to_string()),
+                heading(1, "Tabbed"),
+                heading(1, "Spaced"),
+            ]
+        );
+    }
+
+    #[test]
+    fn more_than_six_hashes_are_text() {
+        assert_eq!(
+            blocks(
+                "####### too many
+###### just enough",
+                true
+            ),
+            [
+                RenderBlock::Paragraph("####### too many".to_string()),
+                heading(6, "just enough"),
+            ]
+        );
+    }
+
+    // Up to three columns of indentation, or any within text, still makes a heading. After a
+    // blank line, a tab is four columns and starts indented code instead
+    #[test]
+    fn indented_headings() {
+        assert_eq!(
+            blocks("   ### Indented\nText\n\t## Tab indented", true),
+            [
+                heading(3, "Indented"),
+                RenderBlock::Paragraph("Text".to_string()),
+                heading(2, "Tab indented"),
+            ]
+        );
+        assert_eq!(
+            blocks("Text\n\n\t# Code", true),
+            [
+                RenderBlock::Paragraph("Text".to_string()),
+                RenderBlock::Gap,
+                RenderBlock::IndentedCode("# Code".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn closing_hashes_are_dropped() {
+        assert_eq!(
+            blocks("## Closed ##\n# C#\n#\n### ###", true),
+            [
+                heading(2, "Closed"),
+                heading(1, "C#"),
+                heading(1, ""),
+                heading(3, ""),
+            ]
+        );
+    }
+
+    // Markup comes out and leaves its text, while markers shown as written stay
+    #[test]
+    fn inline_markup_is_stripped() {
+        let definitions = HashMap::from([("ref".to_string(), "https://ref".to_string())]);
+        let strip = |text| strip_inline_markup(text, &definitions, true);
+        assert_eq!(
+            strip("**Bold** and _it_ in snake_case, 2 * 3, \\*escaped\\* and `a*b*`"),
+            "Bold and it in snake_case, 2 * 3, *escaped* and a*b*"
+        );
+        assert_eq!(strip("~~Gone~~ but ~one stays"), "Gone but ~one stays");
+        assert_eq!(
+            strip_inline_markup("~~Kept~~", &definitions, false),
+            "~~Kept~~"
+        );
+        assert_eq!(
+            strip("See [the **docs**](https://x), ![a logo](logo.png), [ref] and [other][ref]"),
+            "See the docs, a logo, ref and other"
+        );
+        assert_eq!(strip("An [undefined] link"), "An [undefined] link");
+    }
+
+    // Definitions outside code are collected by their normalized label, the first one winning
+    #[test]
+    fn links_resolve_against_their_definitions() {
+        let definitions = link_definitions(concat!(
+            "[Ref  Label]: <https://first>\n[ref label]: https://second\n",
+            "```\n[code]: https://c\n```\n    [indented]: https://i\n[^1]: A footnote",
+        ));
+        assert_eq!(
+            definitions,
+            HashMap::from([("ref label".to_string(), "https://first".to_string())])
+        );
+
+        let link = |rest| parse_link(rest, &definitions);
+        assert_eq!(
+            link("text](url) after"),
+            Some(("text", "url".to_string(), 10))
+        );
+        assert_eq!(
+            link("x][REF label] after"),
+            Some(("x", "https://first".to_string(), 13))
+        );
+        assert_eq!(
+            link("ref label][] after"),
+            Some(("ref label", "https://first".to_string(), 12))
+        );
+        assert_eq!(
+            link("Ref label] after"),
+            Some(("Ref label", "https://first".to_string(), 10))
+        );
+        assert_eq!(link("unknown] after"), None);
+        assert_eq!(link("x][unknown]"), None);
+        assert_eq!(link("a [b](c)"), None);
+        assert_eq!(link(" ](url)"), None);
+    }
+}